@@ -0,0 +1,109 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Builds SQLite from the amalgamation in `sqlite3/` when the `bundled`
+//! feature is enabled; otherwise the system libsqlite3 is linked.
+//!
+//! The bundled version is recorded in `sqlite3/VERSION`. To update it,
+//! replace `sqlite3/sqlite3.c`, `sqlite3/sqlite3.h` and
+//! `sqlite3/sqlite3ext.h` with those from the amalgamation zip at
+//! http://www.sqlite.org/download.html and update `VERSION` to match
+//! `SQLITE_VERSION` and `SQLITE_SOURCE_ID` in `sqlite3.h`.
+
+use std::io::Command;
+use std::io::process::InheritFd;
+use std::os;
+
+/// Compile-time options turned on by each cargo feature.
+static OPTIONS: &'static [(&'static str, &'static str)] = &[
+    ("FTS5",            "SQLITE_ENABLE_FTS5"),
+    ("RTREE",           "SQLITE_ENABLE_RTREE"),
+    ("COLUMN_METADATA", "SQLITE_ENABLE_COLUMN_METADATA"),
+    ("PREUPDATE_HOOK",  "SQLITE_ENABLE_PREUPDATE_HOOK"),
+    ("SESSION",         "SQLITE_ENABLE_SESSION"),
+    ("SESSION",         "SQLITE_ENABLE_PREUPDATE_HOOK"),
+];
+
+fn feature(name: &str) -> bool {
+    os::getenv(format!("CARGO_FEATURE_{}", name).as_slice()).is_some()
+}
+
+/// The program named by environment variable `var`, or `default`.
+fn tool(var: &str, default: &str) -> String {
+    os::getenv(var).unwrap_or(default.to_string())
+}
+
+fn run(cmd: &mut Command) {
+    match cmd.stdout(InheritFd(1)).stderr(InheritFd(2)).status() {
+        Ok(status) => if !status.success() { fail!("`{}` failed: {}", cmd, status) },
+        Err(e) => fail!("could not run `{}`: {}", cmd, e),
+    }
+}
+
+fn main() {
+    if !feature("BUNDLED") {
+        return;
+    }
+    let src = Path::new(os::getenv("CARGO_MANIFEST_DIR").unwrap()).join_many(["sqlite3", "sqlite3.c"]);
+    if !src.exists() {
+        fail!("the bundled feature needs the SQLite amalgamation at {}", src.display());
+    }
+    let out = Path::new(os::getenv("OUT_DIR").unwrap());
+
+    // The crate refuses to open connections with a single-threaded SQLite.
+    let mut defines = vec!["SQLITE_THREADSAFE=1"];
+    for &(name, define) in OPTIONS.iter() {
+        if feature(name) && !defines.contains(&define) {
+            defines.push(define);
+        }
+    }
+
+    // CC, AR and CFLAGS override the defaults, as they would for make
+    let windows = os::getenv("TARGET").map_or(false, |t| t.as_slice().contains("windows"));
+    let obj = out.join("sqlite3.o");
+    let mut cmd = Command::new(tool("CC", "cc").as_slice());
+    cmd.arg("-c").arg("-O2");
+    if !windows {
+        cmd.arg("-fPIC");
+    }
+    for flag in os::getenv("CFLAGS").unwrap_or(String::new()).as_slice().words() {
+        cmd.arg(flag);
+    }
+    for define in defines.iter() {
+        cmd.arg(format!("-D{}", define));
+    }
+    cmd.arg(&src).arg("-o").arg(&obj);
+    run(&mut cmd);
+    run(Command::new(tool("AR", "ar").as_slice()).arg("crs").arg(out.join("libsqlite3.a")).arg(&obj));
+
+    let libs = if windows { "" } else { " -l dl -l pthread -l m" };
+    println!("cargo:rustc-flags=-L native={} -l static=sqlite3{}", out.display(), libs);
+}
@@ -0,0 +1,87 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! A loadable extension that calls SQLite only through the host's API
+//! routines. It registers `halve(x)`, the `reverse` collation and the
+//! `series(n)` virtual table module.
+
+#![feature(phase)]
+
+#[phase(plugin, link)]
+extern crate sqlite3;
+
+use sqlite3::{BindArg, Database, Float64, Integer64, Null, SqliteError, SQLITE_ERROR, SQLITE_MISMATCH};
+use sqlite3::vtab::{Module, VirtualTable};
+
+struct Series;
+
+struct SeriesTable {
+    n: i64,
+}
+
+impl Module for Series {
+    fn connect(&mut self, _schema: &str, _name: &str, args: &[String]) -> Result<Box<VirtualTable + Send>, SqliteError> {
+        match args.get(0).and_then(|a| from_str::<i64>(a.as_slice().trim())) {
+            Some(n) => Ok(box SeriesTable { n: n } as Box<VirtualTable + Send>),
+            None => Err(SqliteError { kind: SQLITE_ERROR, desc: "series needs a length".to_string() })
+        }
+    }
+}
+
+impl VirtualTable for SeriesTable {
+    fn schema(&self) -> String {
+        "CREATE TABLE x (value INTEGER)".to_string()
+    }
+
+    fn rows(&mut self) -> Result<Vec<(i64, Vec<BindArg>)>, SqliteError> {
+        Ok(range(1, self.n + 1).map(|i| (i, vec![Integer64(i)])).collect())
+    }
+}
+
+fn error(kind: sqlite3::ResultError, what: &str) -> SqliteError {
+    SqliteError { kind: kind, desc: format!("cannot register {}", what) }
+}
+
+fn init(database: &Database) -> Result<(), SqliteError> {
+    try!(database.create_function("halve", 1, true, move |&mut: args: &[BindArg]| {
+        match args[0] {
+            Integer64(v) => Ok(Float64(v as f64 / 2.0)),
+            Float64(v) => Ok(Float64(v / 2.0)),
+            Null => Ok(Null),
+            _ => Err(SqliteError { kind: SQLITE_MISMATCH, desc: "halve needs a number".to_string() })
+        }
+    }).map_err(|e| error(e, "halve")));
+    try!(database.create_collation("reverse", move |&mut: a: &str, b: &str| b.cmp(a))
+                 .map_err(|e| error(e, "reverse")));
+    database.create_module("series", Series).map_err(|e| error(e, "series"))
+}
+
+sqlite3_extension_init!(sqlite3_testext_init, init)
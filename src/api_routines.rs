@@ -0,0 +1,491 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Calls into SQLite through the `sqlite3_api_routines` table that the
+//! host passes to a loadable extension's entry point, for builds with the
+//! `loadable_extension` feature. `ffi` re-exports the forwarders defined
+//! here in place of its `extern` declarations, so nothing else in the
+//! crate knows the difference, and no `sqlite3_*` symbols are exported
+//! from the extension.
+
+use ffi::{dbh, stmt, value, backup, context, sqlite3_module, sqlite3_vfs};
+use libc;
+use libc::{c_char, c_int, c_uint, c_void};
+use std::mem;
+use std::sync::atomic::{AtomicUint, INIT_ATOMIC_UINT, SeqCst};
+use types::*;
+
+/// The oldest SQLite providing every entry this crate calls.
+static MIN_VERSION: c_int = 3041000;
+
+/// The address of the host's table, set by `set_routines`. Every connection
+/// in the host shares one table, so the first entry point to run sets it
+/// for the whole extension.
+static ROUTINES: AtomicUint = INIT_ATOMIC_UINT;
+
+/// The layout of `sqlite3_api_routines` from `sqlite3ext.h`, up to the
+/// last entry we need. Entries are cast to the right function type
+/// where they are called.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct sqlite3_api_routines {
+    pub aggregate_context: *const c_void,
+    pub aggregate_count: *const c_void,
+    pub bind_blob: *const c_void,
+    pub bind_double: *const c_void,
+    pub bind_int: *const c_void,
+    pub bind_int64: *const c_void,
+    pub bind_null: *const c_void,
+    pub bind_parameter_count: *const c_void,
+    pub bind_parameter_index: *const c_void,
+    pub bind_parameter_name: *const c_void,
+    pub bind_text: *const c_void,
+    pub bind_text16: *const c_void,
+    pub bind_value: *const c_void,
+    pub busy_handler: *const c_void,
+    pub busy_timeout: *const c_void,
+    pub changes: *const c_void,
+    pub close: *const c_void,
+    pub collation_needed: *const c_void,
+    pub collation_needed16: *const c_void,
+    pub column_blob: *const c_void,
+    pub column_bytes: *const c_void,
+    pub column_bytes16: *const c_void,
+    pub column_count: *const c_void,
+    pub column_database_name: *const c_void,
+    pub column_database_name16: *const c_void,
+    pub column_decltype: *const c_void,
+    pub column_decltype16: *const c_void,
+    pub column_double: *const c_void,
+    pub column_int: *const c_void,
+    pub column_int64: *const c_void,
+    pub column_name: *const c_void,
+    pub column_name16: *const c_void,
+    pub column_origin_name: *const c_void,
+    pub column_origin_name16: *const c_void,
+    pub column_table_name: *const c_void,
+    pub column_table_name16: *const c_void,
+    pub column_text: *const c_void,
+    pub column_text16: *const c_void,
+    pub column_type: *const c_void,
+    pub column_value: *const c_void,
+    pub commit_hook: *const c_void,
+    pub complete: *const c_void,
+    pub complete16: *const c_void,
+    pub create_collation: *const c_void,
+    pub create_collation16: *const c_void,
+    pub create_function: *const c_void,
+    pub create_function16: *const c_void,
+    pub create_module: *const c_void,
+    pub data_count: *const c_void,
+    pub db_handle: *const c_void,
+    pub declare_vtab: *const c_void,
+    pub enable_shared_cache: *const c_void,
+    pub errcode: *const c_void,
+    pub errmsg: *const c_void,
+    pub errmsg16: *const c_void,
+    pub exec: *const c_void,
+    pub expired: *const c_void,
+    pub finalize: *const c_void,
+    pub free: *const c_void,
+    pub free_table: *const c_void,
+    pub get_autocommit: *const c_void,
+    pub get_auxdata: *const c_void,
+    pub get_table: *const c_void,
+    pub global_recover: *const c_void,
+    pub interruptx: *const c_void,
+    pub last_insert_rowid: *const c_void,
+    pub libversion: *const c_void,
+    pub libversion_number: *const c_void,
+    pub malloc: *const c_void,
+    pub mprintf: *const c_void,
+    pub open: *const c_void,
+    pub open16: *const c_void,
+    pub prepare: *const c_void,
+    pub prepare16: *const c_void,
+    pub profile: *const c_void,
+    pub progress_handler: *const c_void,
+    pub realloc: *const c_void,
+    pub reset: *const c_void,
+    pub result_blob: *const c_void,
+    pub result_double: *const c_void,
+    pub result_error: *const c_void,
+    pub result_error16: *const c_void,
+    pub result_int: *const c_void,
+    pub result_int64: *const c_void,
+    pub result_null: *const c_void,
+    pub result_text: *const c_void,
+    pub result_text16: *const c_void,
+    pub result_text16be: *const c_void,
+    pub result_text16le: *const c_void,
+    pub result_value: *const c_void,
+    pub rollback_hook: *const c_void,
+    pub set_authorizer: *const c_void,
+    pub set_auxdata: *const c_void,
+    pub xsnprintf: *const c_void,
+    pub step: *const c_void,
+    pub table_column_metadata: *const c_void,
+    pub thread_cleanup: *const c_void,
+    pub total_changes: *const c_void,
+    pub trace: *const c_void,
+    pub transfer_bindings: *const c_void,
+    pub update_hook: *const c_void,
+    pub user_data: *const c_void,
+    pub value_blob: *const c_void,
+    pub value_bytes: *const c_void,
+    pub value_bytes16: *const c_void,
+    pub value_double: *const c_void,
+    pub value_int: *const c_void,
+    pub value_int64: *const c_void,
+    pub value_numeric_type: *const c_void,
+    pub value_text: *const c_void,
+    pub value_text16: *const c_void,
+    pub value_text16be: *const c_void,
+    pub value_text16le: *const c_void,
+    pub value_type: *const c_void,
+    pub vmprintf: *const c_void,
+    pub overload_function: *const c_void,
+    // Added in 3.3.13
+    pub prepare_v2: *const c_void,
+    pub prepare16_v2: *const c_void,
+    pub clear_bindings: *const c_void,
+    // Added in 3.4.1
+    pub create_module_v2: *const c_void,
+    // Added in 3.5.0
+    pub bind_zeroblob: *const c_void,
+    pub blob_bytes: *const c_void,
+    pub blob_close: *const c_void,
+    pub blob_open: *const c_void,
+    pub blob_read: *const c_void,
+    pub blob_write: *const c_void,
+    pub create_collation_v2: *const c_void,
+    pub file_control: *const c_void,
+    pub memory_highwater: *const c_void,
+    pub memory_used: *const c_void,
+    pub mutex_alloc: *const c_void,
+    pub mutex_enter: *const c_void,
+    pub mutex_free: *const c_void,
+    pub mutex_leave: *const c_void,
+    pub mutex_try: *const c_void,
+    pub open_v2: *const c_void,
+    pub release_memory: *const c_void,
+    pub result_error_nomem: *const c_void,
+    pub result_error_toobig: *const c_void,
+    pub sleep: *const c_void,
+    pub soft_heap_limit: *const c_void,
+    pub vfs_find: *const c_void,
+    pub vfs_register: *const c_void,
+    pub vfs_unregister: *const c_void,
+    pub xthreadsafe: *const c_void,
+    pub result_zeroblob: *const c_void,
+    pub result_error_code: *const c_void,
+    pub test_control: *const c_void,
+    pub randomness: *const c_void,
+    pub context_db_handle: *const c_void,
+    pub extended_result_codes: *const c_void,
+    pub limit: *const c_void,
+    pub next_stmt: *const c_void,
+    pub sql: *const c_void,
+    pub status: *const c_void,
+    // Added in 3.7.4
+    pub backup_finish: *const c_void,
+    pub backup_init: *const c_void,
+    pub backup_pagecount: *const c_void,
+    pub backup_remaining: *const c_void,
+    pub backup_step: *const c_void,
+    pub compileoption_get: *const c_void,
+    pub compileoption_used: *const c_void,
+    pub create_function_v2: *const c_void,
+    pub db_config: *const c_void,
+    pub db_mutex: *const c_void,
+    pub db_status: *const c_void,
+    pub extended_errcode: *const c_void,
+    pub log: *const c_void,
+    pub soft_heap_limit64: *const c_void,
+    pub sourceid: *const c_void,
+    pub stmt_status: *const c_void,
+    pub strnicmp: *const c_void,
+    pub unlock_notify: *const c_void,
+    pub wal_autocheckpoint: *const c_void,
+    pub wal_checkpoint: *const c_void,
+    pub wal_hook: *const c_void,
+    pub blob_reopen: *const c_void,
+    pub vtab_config: *const c_void,
+    pub vtab_on_conflict: *const c_void,
+    // Added in 3.7.16
+    pub close_v2: *const c_void,
+    pub db_filename: *const c_void,
+    pub db_readonly: *const c_void,
+    pub db_release_memory: *const c_void,
+    pub errstr: *const c_void,
+    pub stmt_busy: *const c_void,
+    pub stmt_readonly: *const c_void,
+    pub stricmp: *const c_void,
+    pub uri_boolean: *const c_void,
+    pub uri_int64: *const c_void,
+    pub uri_parameter: *const c_void,
+    pub xvsnprintf: *const c_void,
+    pub wal_checkpoint_v2: *const c_void,
+    // Added in 3.8.7
+    pub auto_extension: *const c_void,
+    pub bind_blob64: *const c_void,
+    pub bind_text64: *const c_void,
+    pub cancel_auto_extension: *const c_void,
+    pub load_extension: *const c_void,
+    pub malloc64: *const c_void,
+    pub msize: *const c_void,
+    pub realloc64: *const c_void,
+    pub reset_auto_extension: *const c_void,
+    pub result_blob64: *const c_void,
+    pub result_text64: *const c_void,
+    pub strglob: *const c_void,
+    // Added in 3.8.11
+    pub value_dup: *const c_void,
+    pub value_free: *const c_void,
+    pub result_zeroblob64: *const c_void,
+    pub bind_zeroblob64: *const c_void,
+    // Added in 3.9.0
+    pub value_subtype: *const c_void,
+    pub result_subtype: *const c_void,
+    // Added in 3.10.0
+    pub status64: *const c_void,
+    pub strlike: *const c_void,
+    pub db_cacheflush: *const c_void,
+    // Added in 3.12.0
+    pub system_errno: *const c_void,
+    // Added in 3.14.0
+    pub trace_v2: *const c_void,
+    pub expanded_sql: *const c_void,
+    // Added in 3.18.0
+    pub set_last_insert_rowid: *const c_void,
+    // Added in 3.20.0
+    pub prepare_v3: *const c_void,
+    pub prepare16_v3: *const c_void,
+    pub bind_pointer: *const c_void,
+    pub result_pointer: *const c_void,
+    pub value_pointer: *const c_void,
+    // Added in 3.22.0
+    pub vtab_nochange: *const c_void,
+    pub value_nochange: *const c_void,
+    pub vtab_collation: *const c_void,
+    // Added in 3.24.0
+    pub keyword_count: *const c_void,
+    pub keyword_name: *const c_void,
+    pub keyword_check: *const c_void,
+    pub str_new: *const c_void,
+    pub str_finish: *const c_void,
+    pub str_appendf: *const c_void,
+    pub str_vappendf: *const c_void,
+    pub str_append: *const c_void,
+    pub str_appendall: *const c_void,
+    pub str_appendchar: *const c_void,
+    pub str_reset: *const c_void,
+    pub str_errcode: *const c_void,
+    pub str_length: *const c_void,
+    pub str_value: *const c_void,
+    // Added in 3.25.0
+    pub create_window_function: *const c_void,
+    // Added in 3.26.0
+    pub normalized_sql: *const c_void,
+    // Added in 3.28.0
+    pub stmt_isexplain: *const c_void,
+    pub value_frombind: *const c_void,
+    // Added in 3.30.0
+    pub drop_modules: *const c_void,
+    // Added in 3.31.0
+    pub hard_heap_limit64: *const c_void,
+    pub uri_key: *const c_void,
+    pub filename_database: *const c_void,
+    pub filename_journal: *const c_void,
+    pub filename_wal: *const c_void,
+    // Added in 3.32.0
+    pub create_filename: *const c_void,
+    pub free_filename: *const c_void,
+    pub database_file_object: *const c_void,
+    // Added in 3.34.0
+    pub txn_state: *const c_void,
+    // Added in 3.36.1
+    pub changes64: *const c_void,
+    pub total_changes64: *const c_void,
+    // Added in 3.37.0
+    pub autovacuum_pages: *const c_void,
+    // Added in 3.38.0
+    pub error_offset: *const c_void,
+    pub vtab_rhs_value: *const c_void,
+    pub vtab_distinct: *const c_void,
+    pub vtab_in: *const c_void,
+    pub vtab_in_first: *const c_void,
+    pub vtab_in_next: *const c_void,
+    // Added in 3.39.0
+    pub deserialize: *const c_void,
+    pub serialize: *const c_void,
+    pub db_name: *const c_void,
+    // Added in 3.40.0
+    pub value_encoding: *const c_void,
+    // Added in 3.41.0
+    pub is_interrupted: *const c_void,
+}
+
+/// Fails with `SQLITE_MISUSE` without a table and with `SQLITE_ERROR`
+/// if the host's SQLite is older than `MIN_VERSION`.
+pub unsafe fn set_routines(api: *const u8) -> SqliteResult<()> {
+    if api.is_null() {
+        return Err(SQLITE_MISUSE);
+    }
+    let api = api as *const sqlite3_api_routines;
+    let libversion_number: extern "C" fn() -> c_int = mem::transmute((*api).libversion_number);
+    if libversion_number() < MIN_VERSION {
+        return Err(SQLITE_ERROR);
+    }
+    ROUTINES.store(api as uint, SeqCst);
+    Ok(())
+}
+
+/// Aborts the process if no entry point has run yet.
+fn routines() -> &'static sqlite3_api_routines {
+    let api = ROUTINES.load(SeqCst);
+    if api == 0 {
+        // We may be inside a callback from SQLite, so failing would unwind
+        // into C.
+        unsafe { libc::abort() }
+    }
+    unsafe { &*(api as *const sqlite3_api_routines) }
+}
+
+/// Stands in for the variadic `sqlite3_db_config` with the arguments of
+/// the boolean options, the only ones the crate passes. The entry itself
+/// is called as the variadic function it is.
+pub unsafe fn sqlite3_db_config(dbh: *mut dbh, op: c_int, onoff: c_int, out: *mut c_int) -> c_int {
+    let f: extern "C" fn(*mut dbh, c_int, ...) -> c_int = mem::transmute(routines().db_config);
+    f(dbh, op, onoff, out)
+}
+
+// Defines each SQLite function the crate calls as a forwarder to its
+// entry in the table, with the signature `ffi` declares it with. They are
+// plain Rust functions, so they do not clash with the host's symbols.
+macro_rules! forward(
+    ($(fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty => $slot:ident;)*) => (
+        $(
+            pub unsafe fn $name($($arg: $ty),*) -> $ret {
+                let f: extern "C" fn($($ty),*) -> $ret = mem::transmute(routines().$slot);
+                f($($arg),*)
+            }
+        )*
+    )
+)
+
+forward! {
+    fn sqlite3_open(path: *const c_char, hnd: *mut *mut dbh) -> c_int => open;
+    fn sqlite3_open_v2(path: *const c_char, hnd: *mut *mut dbh, flags: c_int, vfs: *const c_char) -> c_int => open_v2;
+    fn sqlite3_threadsafe() -> c_int => xthreadsafe;
+    fn sqlite3_libversion() -> *const c_char => libversion;
+    fn sqlite3_libversion_number() -> c_int => libversion_number;
+    fn sqlite3_sourceid() -> *const c_char => sourceid;
+    fn sqlite3_compileoption_used(name: *const c_char) -> c_int => compileoption_used;
+    fn sqlite3_close_v2(dbh: *mut dbh) -> c_int => close_v2;
+    fn sqlite3_errmsg(dbh: *mut dbh) -> *const c_char => errmsg;
+    fn sqlite3_changes(dbh: *mut dbh) -> c_int => changes;
+    fn sqlite3_last_insert_rowid(dbh: *mut dbh) -> i64 => last_insert_rowid;
+    fn sqlite3_complete(sql: *const c_char) -> c_int => complete;
+    fn sqlite3_get_autocommit(dbh: *mut dbh) -> c_int => get_autocommit;
+    fn sqlite3_prepare_v2(hnd: *mut dbh, sql: *const c_char, sql_len: c_int, shnd: *mut *mut stmt, tail: *mut *const c_char) -> c_int => prepare_v2;
+    fn sqlite3_exec(dbh: *mut dbh, sql: *const c_char, cb: Option<extern "C" fn(*mut c_void, c_int, *mut *mut c_char, *mut *mut c_char) -> c_int>, d: *mut c_void, err: *mut *mut c_char) -> c_int => exec;
+    fn sqlite3_free(p: *mut c_void) -> () => free;
+    fn sqlite3_step(sth: *mut stmt) -> c_int => step;
+    fn sqlite3_reset(sth: *mut stmt) -> c_int => reset;
+    fn sqlite3_finalize(sth: *mut stmt) -> c_int => finalize;
+    fn sqlite3_clear_bindings(sth: *mut stmt) -> c_int => clear_bindings;
+    fn sqlite3_column_name(sth: *mut stmt, icol: c_int) -> *const c_char => column_name;
+    fn sqlite3_column_type(sth: *mut stmt, icol: c_int) -> c_int => column_type;
+    fn sqlite3_data_count(sth: *mut stmt) -> c_int => data_count;
+    fn sqlite3_column_count(sth: *mut stmt) -> c_int => column_count;
+    fn sqlite3_column_decltype(sth: *mut stmt, icol: c_int) -> *const c_char => column_decltype;
+    fn sqlite3_column_bytes(sth: *mut stmt, icol: c_int) -> c_int => column_bytes;
+    fn sqlite3_column_blob(sth: *mut stmt, icol: c_int) -> *const u8 => column_blob;
+    fn sqlite3_column_text(sth: *mut stmt, icol: c_int) -> *const c_char => column_text;
+    fn sqlite3_column_double(sth: *mut stmt, icol: c_int) -> f64 => column_double;
+    fn sqlite3_column_int(sth: *mut stmt, icol: c_int) -> c_int => column_int;
+    fn sqlite3_column_int64(sth: *mut stmt, icol: c_int) -> i64 => column_int64;
+    fn sqlite3_bind_blob(sth: *mut stmt, icol: c_int, buf: *const u8, buflen: c_int, d: *mut c_void) -> c_int => bind_blob;
+    fn sqlite3_bind_text(sth: *mut stmt, icol: c_int, buf: *const c_char, buflen: c_int, d: *mut c_void) -> c_int => bind_text;
+    fn sqlite3_bind_null(sth: *mut stmt, icol: c_int) -> c_int => bind_null;
+    fn sqlite3_bind_int(sth: *mut stmt, icol: c_int, v: c_int) -> c_int => bind_int;
+    fn sqlite3_bind_int64(sth: *mut stmt, icol: c_int, v: i64) -> c_int => bind_int64;
+    fn sqlite3_bind_double(sth: *mut stmt, icol: c_int, value: f64) -> c_int => bind_double;
+    fn sqlite3_bind_parameter_index(sth: *mut stmt, name: *const c_char) -> c_int => bind_parameter_index;
+    fn sqlite3_update_hook(dbh: *mut dbh, cb: Option<extern "C" fn(*mut c_void, c_int, *const c_char, *const c_char, i64)>, arg: *mut c_void) -> *mut c_void => update_hook;
+    fn sqlite3_commit_hook(dbh: *mut dbh, cb: Option<extern "C" fn(*mut c_void) -> c_int>, arg: *mut c_void) -> *mut c_void => commit_hook;
+    fn sqlite3_rollback_hook(dbh: *mut dbh, cb: Option<extern "C" fn(*mut c_void)>, arg: *mut c_void) -> *mut c_void => rollback_hook;
+    fn sqlite3_wal_hook(dbh: *mut dbh, cb: Option<extern "C" fn(*mut c_void, *mut dbh, *const c_char, c_int) -> c_int>, arg: *mut c_void) -> *mut c_void => wal_hook;
+    fn sqlite3_value_type(v: *mut value) -> c_int => value_type;
+    fn sqlite3_value_bytes(v: *mut value) -> c_int => value_bytes;
+    fn sqlite3_value_blob(v: *mut value) -> *const u8 => value_blob;
+    fn sqlite3_value_text(v: *mut value) -> *const c_char => value_text;
+    fn sqlite3_value_double(v: *mut value) -> f64 => value_double;
+    fn sqlite3_value_int64(v: *mut value) -> i64 => value_int64;
+    fn sqlite3_trace_v2(dbh: *mut dbh, mask: c_uint, cb: Option<extern "C" fn(c_uint, *mut c_void, *mut c_void, *mut c_void) -> c_int>, arg: *mut c_void) -> c_int => trace_v2;
+    fn sqlite3_sql(sth: *mut stmt) -> *const c_char => sql;
+    fn sqlite3_expanded_sql(sth: *mut stmt) -> *mut c_char => expanded_sql;
+    fn sqlite3_set_authorizer(dbh: *mut dbh, cb: Option<extern "C" fn(*mut c_void, c_int, *const c_char, *const c_char, *const c_char, *const c_char) -> c_int>, arg: *mut c_void) -> c_int => set_authorizer;
+    fn sqlite3_wal_checkpoint_v2(dbh: *mut dbh, db_name: *const c_char, mode: c_int, log: *mut c_int, ckpt: *mut c_int) -> c_int => wal_checkpoint_v2;
+    fn sqlite3_wal_autocheckpoint(dbh: *mut dbh, n: c_int) -> c_int => wal_autocheckpoint;
+    fn sqlite3_backup_init(dest: *mut dbh, dest_name: *const c_char, source: *mut dbh, source_name: *const c_char) -> *mut backup => backup_init;
+    fn sqlite3_backup_step(b: *mut backup, pages: c_int) -> c_int => backup_step;
+    fn sqlite3_backup_finish(b: *mut backup) -> c_int => backup_finish;
+    fn sqlite3_load_extension(dbh: *mut dbh, path: *const c_char, entry_point: *const c_char, err: *mut *mut c_char) -> c_int => load_extension;
+    fn sqlite3_malloc64(n: u64) -> *mut c_void => malloc64;
+    fn sqlite3_serialize(dbh: *mut dbh, schema: *const c_char, size: *mut i64, flags: c_uint) -> *mut u8 => serialize;
+    fn sqlite3_deserialize(dbh: *mut dbh, schema: *const c_char, data: *mut u8, size: i64, buf_size: i64, flags: c_uint) -> c_int => deserialize;
+    fn sqlite3_errcode(dbh: *mut dbh) -> c_int => errcode;
+    fn sqlite3_busy_timeout(dbh: *mut dbh, ms: c_int) -> c_int => busy_timeout;
+    fn sqlite3_busy_handler(dbh: *mut dbh, cb: Option<extern "C" fn(*mut c_void, c_int) -> c_int>, arg: *mut c_void) -> c_int => busy_handler;
+    fn sqlite3_create_function_v2(dbh: *mut dbh, name: *const c_char, n_arg: c_int, flags: c_int, app: *mut c_void, func: Option<extern "C" fn(*mut context, c_int, *mut *mut value)>, step: Option<extern "C" fn(*mut context, c_int, *mut *mut value)>, fin: Option<extern "C" fn(*mut context)>, destroy: Option<extern "C" fn(*mut c_void)>) -> c_int => create_function_v2;
+    fn sqlite3_create_collation_v2(dbh: *mut dbh, name: *const c_char, text_rep: c_int, arg: *mut c_void, compare: Option<extern "C" fn(*mut c_void, c_int, *const c_void, c_int, *const c_void) -> c_int>, destroy: Option<extern "C" fn(*mut c_void)>) -> c_int => create_collation_v2;
+    fn sqlite3_create_module_v2(dbh: *mut dbh, name: *const c_char, module: *const sqlite3_module, aux: *mut c_void, destroy: Option<extern "C" fn(*mut c_void)>) -> c_int => create_module_v2;
+    fn sqlite3_declare_vtab(dbh: *mut dbh, sql: *const c_char) -> c_int => declare_vtab;
+    fn sqlite3_user_data(ctx: *mut context) -> *mut c_void => user_data;
+    fn sqlite3_result_null(ctx: *mut context) -> () => result_null;
+    fn sqlite3_result_int64(ctx: *mut context, v: i64) -> () => result_int64;
+    fn sqlite3_result_double(ctx: *mut context, v: f64) -> () => result_double;
+    fn sqlite3_result_text(ctx: *mut context, v: *const c_char, n: c_int, d: *mut c_void) -> () => result_text;
+    fn sqlite3_result_blob(ctx: *mut context, v: *const c_void, n: c_int, d: *mut c_void) -> () => result_blob;
+    fn sqlite3_result_error(ctx: *mut context, msg: *const c_char, n: c_int) -> () => result_error;
+    fn sqlite3_result_error_code(ctx: *mut context, code: c_int) -> () => result_error_code;
+    fn sqlite3_interrupt(dbh: *mut dbh) -> () => interruptx;
+    fn sqlite3_is_interrupted(dbh: *mut dbh) -> c_int => is_interrupted;
+    fn sqlite3_progress_handler(dbh: *mut dbh, n_ops: c_int, cb: Option<extern "C" fn(*mut c_void) -> c_int>, arg: *mut c_void) -> () => progress_handler;
+    fn sqlite3_column_database_name(sth: *mut stmt, icol: c_int) -> *const c_char => column_database_name;
+    fn sqlite3_column_table_name(sth: *mut stmt, icol: c_int) -> *const c_char => column_table_name;
+    fn sqlite3_column_origin_name(sth: *mut stmt, icol: c_int) -> *const c_char => column_origin_name;
+    fn sqlite3_table_column_metadata(dbh: *mut dbh, db_name: *const c_char, table_name: *const c_char, column_name: *const c_char, data_type: *mut *const c_char, coll_seq: *mut *const c_char, not_null: *mut c_int, primary_key: *mut c_int, autoinc: *mut c_int) -> c_int => table_column_metadata;
+    fn sqlite3_vfs_find(name: *const c_char) -> *mut sqlite3_vfs => vfs_find;
+    fn sqlite3_vfs_register(vfs: *mut sqlite3_vfs, make_default: c_int) -> c_int => vfs_register;
+}
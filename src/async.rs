@@ -0,0 +1,336 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! A non-blocking facade over a connection, enabled by the `async` cargo
+//! feature.
+//!
+//! `AsyncDatabase` sends each request to a `DatabaseWorker` and returns at
+//! once with a `Pending` result. A `Pending` can be polled, waited on or
+//! turned into a `std::sync::Future`; it does not need an event loop.
+//! Cancelling one, or dropping it unfinished, skips the request if it has
+//! not started yet and interrupts it with `sqlite3_interrupt` otherwise.
+//!
+//! The methods return `Pending` rather than `std::sync::Future` because a
+//! `Future` cannot be cancelled: it has no method for it, and dropping one
+//! does not tell the worker. Call `into_future` where a `Future` is needed
+//! and cancellation is not.
+
+use std::comm::{Empty, Disconnected};
+use std::sync::{Arc, Future, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUint, SeqCst};
+
+use cursor::Cursor;
+use database::{Database, InterruptHandle};
+use types::*;
+use worker::{DatabaseWorker, WorkerResult, WorkerCancelled, WorkerOpenFailed, WorkerStopped};
+
+struct Shared {
+    // id of the request running on the worker, 0 if none
+    running: Mutex<uint>,
+    next_id: AtomicUint,
+    interrupt: InterruptHandle,
+}
+
+/// Marks the worker idle when a request finishes, even if it fails.
+struct Running {
+    shared: Arc<Shared>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        *self.shared.running.lock() = 0;
+    }
+}
+
+/// The eventual result of a request to an `AsyncDatabase`. A request that
+/// was cancelled before it started yields `Err(WorkerCancelled)`; one that
+/// was interrupted yields `SQLITE_INTERRUPT` from SQLite.
+pub struct Pending<T> {
+    id: uint,
+    // `None` once the result has been taken
+    rx: Option<Receiver<WorkerResult<Option<T>>>>,
+    cancelled: Arc<AtomicBool>,
+    shared: Arc<Shared>,
+}
+
+fn flatten<T>(r: Result<WorkerResult<Option<T>>, ()>) -> WorkerResult<T> {
+    match r {
+        Ok(Ok(Some(v))) => Ok(v),
+        Ok(Ok(None)) => Err(WorkerCancelled),
+        Ok(Err(e)) => Err(e),
+        Err(()) => Err(WorkerStopped),
+    }
+}
+
+impl<T: Send> Pending<T> {
+    /// Returns the result if it is ready.
+    pub fn poll(&mut self) -> Option<WorkerResult<T>> {
+        let r = match self.rx.as_ref().unwrap().try_recv() {
+            Ok(r) => Ok(r),
+            Err(Empty) => return None,
+            Err(Disconnected) => Err(()),
+        };
+        self.rx = None;
+        Some(flatten(r))
+    }
+
+    /// Waits for the result.
+    pub fn wait(mut self) -> WorkerResult<T> {
+        flatten(self.rx.take().unwrap().recv_opt())
+    }
+
+    /// Converts into a `std::sync::Future`. The request can no longer be
+    /// cancelled.
+    pub fn into_future(mut self) -> Future<WorkerResult<T>> {
+        let rx = self.rx.take().unwrap();
+        Future::from_fn(proc() flatten(rx.recv_opt()))
+    }
+
+    /// Skips the request if it has not started, or interrupts the
+    /// statement it is running. A request between statements is not
+    /// interrupted, so callers may need to cancel again.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, SeqCst);
+        let running = self.shared.running.lock();
+        if *running == self.id {
+            self.shared.interrupt.interrupt();
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<T: Send> Drop for Pending<T> {
+    fn drop(&mut self) {
+        if self.rx.is_some() {
+            self.cancel();
+        }
+    }
+}
+
+/// A connection that answers requests on a background thread.
+pub struct AsyncDatabase {
+    worker: DatabaseWorker,
+    shared: Arc<Shared>,
+}
+
+fn bind(cursor: &Cursor, params: &[BindArg]) -> Result<(), SqliteError> {
+    cursor.bind_params(params).map_err(|e| SqliteError { kind: e, desc: String::new() })
+}
+
+fn sqlite(database: &Database, kind: ResultError) -> SqliteError {
+    SqliteError { kind: kind, desc: database.get_errmsg() }
+}
+
+impl AsyncDatabase {
+    /// Opens `path` on a new worker thread.
+    pub fn open(path: &str) -> SqliteResult<AsyncDatabase> {
+        let worker = match DatabaseWorker::spawn(path) {
+            Ok(worker) => worker,
+            Err(WorkerOpenFailed(e)) => return Err(e),
+            Err(_) => return Err(SQLITE_ERROR)
+        };
+        let interrupt = match worker.call(proc(db: &Database) db.interrupt_handle()).recv() {
+            Ok(handle) => handle,
+            Err(_) => return Err(SQLITE_MISUSE)
+        };
+        Ok(AsyncDatabase {
+            worker: worker,
+            shared: Arc::new(Shared {
+                running: Mutex::new(0),
+                next_id: AtomicUint::new(1),
+                interrupt: interrupt,
+            }),
+        })
+    }
+
+    /// Runs `f` against the connection on the worker thread.
+    pub fn call<T: Send>(&self, f: proc(&Database): Send -> T) -> Pending<T> {
+        let id = self.shared.next_id.fetch_add(1, SeqCst);
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        let shared = self.shared.clone();
+        let job_cancelled = cancelled.clone();
+        let rx = self.worker.call(proc(database: &Database) {
+            {
+                let mut running = shared.running.lock();
+                if job_cancelled.load(SeqCst) {
+                    return None;
+                }
+                *running = id;
+            }
+            let _running = Running { shared: shared };
+            Some(f(database))
+        });
+
+        Pending { id: id, rx: Some(rx), cancelled: cancelled, shared: self.shared.clone() }
+    }
+
+    /// Runs a statement with `params` and returns the number of rows it
+    /// changed.
+    pub fn execute(&self, sql: &str, params: Vec<BindArg>) -> Pending<Result<int, SqliteError>> {
+        let sql = sql.to_string();
+        self.call(proc(database: &Database) {
+            let cursor = try!(database.prepare(sql.as_slice()).map_err(|e| sqlite(database, e)));
+            try!(bind(&cursor, params.as_slice()));
+            try!(cursor.step().map_err(|e| sqlite(database, e)));
+            Ok(database.get_changes())
+        })
+    }
+
+    /// Runs a query with `params` and maps every row with `f`, which runs
+    /// on the worker thread.
+    pub fn query_map<T: Send, F>(&self, sql: &str, params: Vec<BindArg>, f: F)
+                                 -> Pending<Result<Vec<T>, SqliteError>>
+            where F: for<'a, 'db> FnMut<(&'a Cursor<'db>,), T> + Send {
+        let sql = sql.to_string();
+        self.call(proc(database: &Database) {
+            let mut f = f;
+            let cursor = try!(database.prepare(sql.as_slice()).map_err(|e| sqlite(database, e)));
+            try!(bind(&cursor, params.as_slice()));
+            let mut rows = Vec::new();
+            loop {
+                match try!(cursor.step().map_err(|e| sqlite(database, e))) {
+                    SQLITE_ROW => rows.push(f.call_mut((&cursor,))),
+                    SQLITE_DONE => return Ok(rows)
+                }
+            }
+        })
+    }
+
+    /// Runs `f` inside a transaction, committing if it succeeds and
+    /// rolling back if it fails or returns an error.
+    pub fn transaction<T: Send>(&self, f: proc(&Database): Send -> Result<T, SqliteError>)
+                                -> Pending<Result<T, SqliteError>> {
+        self.call(proc(database: &Database) {
+            try!(database.exec_with_message("BEGIN"));
+            match f(database) {
+                Ok(v) => {
+                    match database.exec_with_message("COMMIT") {
+                        Ok(()) => Ok(v),
+                        Err(e) => {
+                            let _ = database.exec("ROLLBACK");
+                            Err(e)
+                        }
+                    }
+                }
+                Err(e) => {
+                    let _ = database.exec("ROLLBACK");
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    /// Copies the "main" database to a new database at `path`.
+    pub fn backup(&self, path: &str) -> Pending<SqliteResult<()>> {
+        let path = path.to_string();
+        self.call(proc(database: &Database) {
+            let dest = try!(Database::new(path.as_slice()));
+            database.backup_to("main", &dest)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cursor::Cursor;
+    use database::Database;
+    use std::io::{timer, TempDir};
+    use std::time::Duration;
+    use types::*;
+    use worker::{WorkerPanicked, WorkerCancelled};
+    use super::*;
+
+    #[test]
+    fn execute_query_and_transaction() {
+        let db = AsyncDatabase::open(":memory:").unwrap();
+        assert_eq!(db.execute("CREATE TABLE test (v TEXT)", vec!()).wait(), Ok(Ok(0)));
+        assert_eq!(db.execute("INSERT INTO test VALUES (?)", vec!(Text("a".to_string()))).wait(), Ok(Ok(1)));
+
+        let failed = db.transaction(proc(db: &Database) {
+            try!(db.exec_with_message("INSERT INTO test VALUES ('b')"));
+            db.exec_with_message("INSERT INTO nosuch VALUES (1)")
+        });
+        assert_eq!(failed.wait().unwrap().unwrap_err().kind, SQLITE_ERROR);
+
+        let suffix = "!".to_string();
+        let mut pending = db.query_map("SELECT v FROM test", vec!(), move |&mut: cursor: &Cursor| {
+            cursor.get_text(0) + suffix.as_slice()
+        });
+        let mut rows = None;
+        while rows.is_none() {
+            rows = pending.poll();
+        }
+        assert_eq!(rows, Some(Ok(Ok(vec!("a!".to_string())))));
+    }
+
+    #[test]
+    fn cancel_running_request() {
+        let db = AsyncDatabase::open(":memory:").unwrap();
+        let (started_tx, started_rx) = channel();
+        let long = db.call(proc(db: &Database) {
+            let cursor = db.prepare(
+                "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c)
+                 SELECT count(*) FROM c").unwrap();
+            started_tx.send(());
+            cursor.step()
+        });
+        let queued = db.call(proc(_db: &Database) 1i);
+        let mut long = long;
+        started_rx.recv();
+        queued.cancel();
+        let mut r = None;
+        while r.is_none() {
+            long.cancel();
+            timer::sleep(Duration::milliseconds(1));
+            r = long.poll();
+        }
+        assert_eq!(r, Some(Ok(Err(SQLITE_INTERRUPT))));
+        assert_eq!(queued.wait(), Err(WorkerCancelled));
+
+        assert_eq!(db.call(proc(_db: &Database) fail!("boom")).wait(),
+                   Err::<(), _>(WorkerPanicked("boom".to_string())));
+        // A failed request does not stay marked as running.
+        assert_eq!(*db.shared.running.lock(), 0);
+    }
+
+    #[test]
+    fn backup_to_file() {
+        let dir = TempDir::new("rustsqlite").unwrap();
+        let path = dir.path().join("backup.db");
+        let db = AsyncDatabase::open(":memory:").unwrap();
+        db.execute("CREATE TABLE test (v)", vec!()).wait().unwrap().unwrap();
+        assert_eq!(db.backup(path.as_str().unwrap()).into_future().get(), Ok(Ok(())));
+
+        let copy = Database::new(path.as_str().unwrap()).unwrap();
+        assert!(copy.prepare("SELECT v FROM test").is_ok());
+    }
+}
@@ -0,0 +1,280 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+use std::ascii::StrAsciiExt;
+use std::collections::HashSet;
+
+/// An operation SQLite asks permission for while compiling a statement,
+/// along with the names it concerns.
+/// See http://www.sqlite.org/c3ref/c_alter_table.html
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum AuthAction {
+    /// Index name, table name.
+    AuthCreateIndex(String, String),
+    /// Table name.
+    AuthCreateTable(String),
+    /// Index name, table name.
+    AuthCreateTempIndex(String, String),
+    /// Table name.
+    AuthCreateTempTable(String),
+    /// Trigger name, table name.
+    AuthCreateTempTrigger(String, String),
+    /// View name.
+    AuthCreateTempView(String),
+    /// Trigger name, table name.
+    AuthCreateTrigger(String, String),
+    /// View name.
+    AuthCreateView(String),
+    /// Table name.
+    AuthDelete(String),
+    /// Index name, table name.
+    AuthDropIndex(String, String),
+    /// Table name.
+    AuthDropTable(String),
+    /// Index name, table name.
+    AuthDropTempIndex(String, String),
+    /// Table name.
+    AuthDropTempTable(String),
+    /// Trigger name, table name.
+    AuthDropTempTrigger(String, String),
+    /// View name.
+    AuthDropTempView(String),
+    /// Trigger name, table name.
+    AuthDropTrigger(String, String),
+    /// View name.
+    AuthDropView(String),
+    /// Table name.
+    AuthInsert(String),
+    /// Pragma name, argument if one was given.
+    AuthPragma(String, Option<String>),
+    /// Table name, column name.
+    AuthRead(String, String),
+    AuthSelect,
+    /// "BEGIN", "COMMIT" or "ROLLBACK".
+    AuthTransaction(String),
+    /// Table name, column name.
+    AuthUpdate(String, String),
+    /// File name.
+    AuthAttach(String),
+    /// Database name.
+    AuthDetach(String),
+    /// Database name, table name.
+    AuthAlterTable(String, String),
+    /// Index name.
+    AuthReindex(String),
+    /// Table name.
+    AuthAnalyze(String),
+    /// Table name, module name.
+    AuthCreateVtable(String, String),
+    /// Table name, module name.
+    AuthDropVtable(String, String),
+    /// Function name.
+    AuthFunction(String),
+    /// "BEGIN", "RELEASE" or "ROLLBACK", savepoint name.
+    AuthSavepoint(String, String),
+    AuthRecursive,
+    /// An action code this binding does not know about, with its raw
+    /// arguments.
+    AuthUnknown(int, Option<String>, Option<String>),
+}
+
+impl AuthAction {
+    /// Builds an action from the code and the first two arguments passed
+    /// to an authorizer callback.
+    pub fn from_code(code: int, arg1: Option<String>, arg2: Option<String>) -> AuthAction {
+        let s = |arg: &Option<String>| arg.clone().unwrap_or(String::new());
+        let (a, b) = (s(&arg1), s(&arg2));
+        match code {
+            1  => AuthCreateIndex(a, b),
+            2  => AuthCreateTable(a),
+            3  => AuthCreateTempIndex(a, b),
+            4  => AuthCreateTempTable(a),
+            5  => AuthCreateTempTrigger(a, b),
+            6  => AuthCreateTempView(a),
+            7  => AuthCreateTrigger(a, b),
+            8  => AuthCreateView(a),
+            9  => AuthDelete(a),
+            10 => AuthDropIndex(a, b),
+            11 => AuthDropTable(a),
+            12 => AuthDropTempIndex(a, b),
+            13 => AuthDropTempTable(a),
+            14 => AuthDropTempTrigger(a, b),
+            15 => AuthDropTempView(a),
+            16 => AuthDropTrigger(a, b),
+            17 => AuthDropView(a),
+            18 => AuthInsert(a),
+            19 => AuthPragma(a, arg2),
+            20 => AuthRead(a, b),
+            21 => AuthSelect,
+            22 => AuthTransaction(a),
+            23 => AuthUpdate(a, b),
+            24 => AuthAttach(a),
+            25 => AuthDetach(a),
+            26 => AuthAlterTable(a, b),
+            27 => AuthReindex(a),
+            28 => AuthAnalyze(a),
+            29 => AuthCreateVtable(a, b),
+            30 => AuthDropVtable(a, b),
+            31 => AuthFunction(b),
+            32 => AuthSavepoint(a, b),
+            33 => AuthRecursive,
+            _  => AuthUnknown(code, arg1, arg2),
+        }
+    }
+}
+
+/// A request passed to an authorizer callback.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub struct AuthContext {
+    pub action: AuthAction,
+    /// The database the action applies to, e.g. "main" or "temp".
+    pub database_name: Option<String>,
+    /// The innermost trigger or view responsible for the access, if any.
+    pub accessor: Option<String>,
+}
+
+/// The answer of an authorizer callback.
+#[deriving(PartialEq, Eq, Show, Clone)]
+#[repr(C)]
+pub enum Authorization {
+    /// Allow the action.
+    AuthAllow  = 0,
+    /// Fail the whole statement with `SQLITE_AUTH`.
+    AuthDeny   = 1,
+    /// Disallow the action but carry on; reads of a column yield NULL.
+    AuthIgnore = 2,
+}
+
+/// Pragmas that only report state when called without an argument.
+static READ_ONLY_PRAGMAS: &'static [&'static str] = &[
+    "application_id", "collation_list", "compile_options", "data_version",
+    "database_list", "encoding", "foreign_key_list", "freelist_count",
+    "function_list", "index_info", "index_list", "index_xinfo",
+    "module_list", "page_count", "page_size", "pragma_list",
+    "schema_version", "table_info", "table_list", "table_xinfo",
+    "user_version",
+];
+
+/// An authorizer policy that lets statements read data but not change it.
+/// Writes, schema changes, ATTACH/DETACH, pragmas that set state or are
+/// not known to be harmless, and calls to functions outside the allowlist
+/// are all denied.
+pub struct ReadOnlyPolicy {
+    functions: HashSet<String>,
+}
+
+impl ReadOnlyPolicy {
+    /// Creates a policy allowing calls to the given functions only.
+    /// Function names are matched case-insensitively.
+    pub fn new(functions: &[&str]) -> ReadOnlyPolicy {
+        ReadOnlyPolicy {
+            functions: functions.iter().map(|f| f.to_ascii_lower()).collect(),
+        }
+    }
+
+    /// Decides on a single request.
+    pub fn authorize(&self, ctx: &AuthContext) -> Authorization {
+        match ctx.action {
+            AuthSelect | AuthRead(..) | AuthRecursive
+                | AuthTransaction(..) | AuthSavepoint(..) => AuthAllow,
+            AuthPragma(ref name, None) => {
+                let name = name.as_slice().to_ascii_lower();
+                if READ_ONLY_PRAGMAS.contains(&name.as_slice()) { AuthAllow } else { AuthDeny }
+            }
+            AuthFunction(ref name) => {
+                if self.functions.contains(&name.as_slice().to_ascii_lower()) { AuthAllow } else { AuthDeny }
+            }
+            _ => AuthDeny
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::Database;
+    use types::{SQLITE_AUTH, SQLITE_ROW};
+    use super::*;
+
+    fn open() -> Database {
+        let database = Database::new(":memory:").unwrap();
+        database.exec("CREATE TABLE test (id INTEGER, v TEXT);
+                       INSERT INTO test VALUES (1, 'a');").unwrap();
+        database
+    }
+
+    #[test]
+    fn authorizer_sees_actions() {
+        let database = open();
+        let (tx, rx) = channel();
+        assert_eq!(database.set_authorizer(move |&mut: ctx: AuthContext| {
+            tx.send(ctx.action);
+            AuthAllow
+        }), Ok(()));
+        database.exec("UPDATE test SET v = 'b'").unwrap();
+        assert_eq!(database.clear_authorizer(), Ok(()));
+
+        let actions = rx.iter().collect::<Vec<AuthAction>>();
+        assert!(actions.contains(&AuthUpdate("test".to_string(), "v".to_string())));
+    }
+
+    #[test]
+    fn authorizer_ignore_hides_column() {
+        let database = open();
+        database.set_authorizer(move |&mut: ctx: AuthContext| {
+            match ctx.action {
+                AuthRead(_, ref col) if col.as_slice() == "v" => AuthIgnore,
+                _ => AuthAllow
+            }
+        }).unwrap();
+        let sth = database.prepare("SELECT id, v FROM test").unwrap();
+        assert_eq!(sth.step(), Ok(SQLITE_ROW));
+        assert_eq!(sth.get_int(0), 1);
+        assert_eq!(sth.get_text(1).as_slice(), "");
+    }
+
+    #[test]
+    fn read_only_policy() {
+        let database = open();
+        database.set_read_only_authorizer(&["count", "lower"]).unwrap();
+
+        assert!(database.exec("SELECT count(*), lower(v) FROM test").is_ok());
+        assert!(database.exec("PRAGMA user_version").is_ok());
+        for sql in ["INSERT INTO test VALUES (2, 'b')",
+                    "DELETE FROM test",
+                    "CREATE TABLE other (x)",
+                    "ATTACH ':memory:' AS other",
+                    "PRAGMA user_version = 3",
+                    "PRAGMA journal_mode = OFF",
+                    "SELECT upper(v) FROM test"].iter() {
+            assert_eq!(database.exec(*sql), Err(SQLITE_AUTH));
+        }
+    }
+}
@@ -38,10 +38,12 @@ use std::slice;
 
 use ffi::*;
 use types::*;
+use util::*;
 
-/// The database cursor.
+/// The database cursor. It borrows the connection it was prepared on.
 pub struct Cursor<'db> {
     stmt: *mut stmt,
+    _dbh: &'db *mut dbh,
 }
 
 #[unsafe_destructor]
@@ -58,9 +60,9 @@ impl<'db> Drop for Cursor<'db> {
 
 impl<'db> Cursor<'db> {
     #[allow(visible_private_types)]
-    pub fn new<'db>(stmt: *mut stmt, _dbh: &'db *mut dbh) -> Cursor<'db> {
+    pub fn new<'db>(stmt: *mut stmt, dbh: &'db *mut dbh) -> Cursor<'db> {
         debug!("`Cursor.new()`: stmt={:?}", stmt);
-        Cursor { stmt: stmt }
+        Cursor { stmt: stmt, _dbh: dbh }
     }
 
     /// Resets a prepared SQL statement, but does not reset its bindings.
@@ -190,9 +192,19 @@ impl<'db> Cursor<'db> {
         }
     }
 
-    /// Returns the number of columns in a result set.
-    /// See http://www.sqlite.org/c3ref/data_count.html
+    /// Returns the number of columns in a result set. This is known as soon
+    /// as the statement is prepared.
+    /// See http://www.sqlite.org/c3ref/column_count.html
     pub fn get_column_count(&self) -> int {
+        unsafe {
+            return sqlite3_column_count(self.stmt) as int;
+        }
+    }
+
+    /// Returns the number of columns in the current row, which is 0 unless
+    /// the last call to `step` returned `SQLITE_ROW`.
+    /// See http://www.sqlite.org/c3ref/data_count.html
+    pub fn get_data_count(&self) -> int {
         unsafe {
             return sqlite3_data_count(self.stmt) as int;
         }
@@ -207,6 +219,46 @@ impl<'db> Cursor<'db> {
         }
     }
 
+    /// Returns the declared type of the column with index `i` in the result
+    /// set, or `None` if it is an expression or has no declared type.
+    /// See http://www.sqlite.org/c3ref/column_decltype.html
+    pub fn get_column_decltype(&self, i: int) -> Option<String> {
+        unsafe {
+            opt_string(sqlite3_column_decltype(self.stmt, i as c_int))
+        }
+    }
+
+    /// Returns the database that the column with index `i` in the result set
+    /// comes from, or `None` if it is an expression.
+    /// See http://www.sqlite.org/c3ref/column_database_name.html
+    #[cfg(feature = "column_metadata")]
+    pub fn get_column_database_name(&self, i: int) -> Option<String> {
+        unsafe {
+            opt_string(sqlite3_column_database_name(self.stmt, i as c_int))
+        }
+    }
+
+    /// Returns the table that the column with index `i` in the result set
+    /// comes from, or `None` if it is an expression.
+    /// See http://www.sqlite.org/c3ref/column_database_name.html
+    #[cfg(feature = "column_metadata")]
+    pub fn get_column_table_name(&self, i: int) -> Option<String> {
+        unsafe {
+            opt_string(sqlite3_column_table_name(self.stmt, i as c_int))
+        }
+    }
+
+    /// Returns the table column, before any `AS` alias, that the column with
+    /// index `i` in the result set comes from, or `None` if it is an
+    /// expression.
+    /// See http://www.sqlite.org/c3ref/column_database_name.html
+    #[cfg(feature = "column_metadata")]
+    pub fn get_column_origin_name(&self, i: int) -> Option<String> {
+        unsafe {
+            opt_string(sqlite3_column_origin_name(self.stmt, i as c_int))
+        }
+    }
+
     /// Returns the type of the column with index `i` in the result set.
     /// See http://www.sqlite.org/c3ref/column_blob.html
     pub fn get_column_type(&self, i: int) -> ColumnType {
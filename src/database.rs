@@ -29,16 +29,174 @@
 ** POSSIBILITY OF SUCH DAMAGE.
 */
 
+use authorizer::*;
 use cursor::*;
 use ffi::*;
-use libc::c_int;
+use libc::{c_char, c_int, c_uint, c_void};
+use std::cell::RefCell;
+use std::cmp;
+use std::cmp::{Ordering, Less, Equal, Greater};
+use std::i32;
+use std::mem;
+use std::num::from_uint;
 use std::ptr;
+use std::raw;
+use std::slice;
 use std::string;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time;
+#[cfg(feature = "session")]
+#[cfg(not(feature = "loadable_extension"))]
+use session::*;
 use types::*;
+use util::*;
+use vtab;
+use vtab::Module;
+use super::threading_mode;
+
+static SQLITE_DBCONFIG_ENABLE_LOAD_EXTENSION: c_int = 1005;
+static SQLITE_SERIALIZE_NOCOPY: c_uint = 0x001;
+static SQLITE_DESERIALIZE_FREEONCLOSE: c_uint = 1;
+static SQLITE_DESERIALIZE_RESIZEABLE: c_uint = 2;
+static SQLITE_DESERIALIZE_READONLY: c_uint = 4;
+static SQLITE_UTF8: c_int = 1;
+static SQLITE_DETERMINISTIC: c_int = 0x800;
+
+/// The closure type accepted by `Database::exec_with_callback`.
+/// It receives the column names and the values of one result row and
+/// returns `false` to abort the remaining statements.
+pub type ExecCallback<'a> = |&[String], &[Option<String>]|: 'a -> bool;
+
+/// A progress handler, see `Database::set_progress_handler`.
+pub type ProgressHandler = Box<FnMut<(), bool> + Send>;
+
+/// A busy handler, see `Database::set_busy_handler`.
+pub type BusyHandler = Box<FnMut<(int,), bool> + Send>;
+
+/// An update hook, see `Database::set_update_hook`.
+pub type UpdateHook = Box<FnMut<(UpdateOperation, String, String, i64), ()> + Send>;
+
+/// A commit hook, see `Database::set_commit_hook`.
+pub type CommitHook = Box<FnMut<(), bool> + Send>;
+
+/// A rollback hook, see `Database::set_rollback_hook`.
+pub type RollbackHook = Box<FnMut<(), ()> + Send>;
+
+/// A WAL hook, see `Database::set_wal_hook`.
+pub type WalHook = Box<FnMut<(String, int), ()> + Send>;
+
+/// A trace callback, see `Database::trace`.
+pub type TraceCallback = Box<FnMut<(TraceEvent,), ()> + Send>;
+
+/// An authorizer callback, see `Database::set_authorizer`.
+pub type Authorizer = Box<FnMut<(AuthContext,), Authorization> + Send>;
+
+/// An SQL function, see `Database::create_function`.
+pub type ScalarFunction = Box<for<'a> FnMut<(&'a [BindArg],), Result<BindArg, SqliteError>> + Send>;
+
+/// A collating sequence, see `Database::create_collation`.
+pub type Collation = Box<for<'a, 'b> FnMut<(&'a str, &'b str), Ordering> + Send>;
+
+/// A preupdate hook, see `Database::set_preupdate_hook`.
+#[cfg(feature = "preupdate_hook")]
+#[cfg(not(feature = "loadable_extension"))]
+pub type PreUpdateHook = Box<for<'a> FnMut<(&'a PreUpdate,), ()> + Send>;
+
+/// The row change being reported to a `PreUpdateHook`. It is only valid
+/// for the duration of the call.
+#[cfg(feature = "preupdate_hook")]
+#[cfg(not(feature = "loadable_extension"))]
+pub struct PreUpdate {
+    dbh: *mut dbh,
+    op: UpdateOperation,
+    db_name: String,
+    table_name: String,
+    old_rowid: i64,
+    new_rowid: i64,
+}
+
+#[cfg(feature = "preupdate_hook")]
+#[cfg(not(feature = "loadable_extension"))]
+impl PreUpdate {
+    /// Returns whether the row is being inserted, updated or deleted.
+    pub fn operation(&self) -> UpdateOperation { self.op }
+
+    /// Returns the name of the database holding the table, e.g. "main".
+    pub fn database_name(&self) -> &str { self.db_name.as_slice() }
+
+    /// Returns the name of the table being changed.
+    pub fn table_name(&self) -> &str { self.table_name.as_slice() }
+
+    /// Returns the rowid before the change (undefined for inserts).
+    pub fn old_rowid(&self) -> i64 { self.old_rowid }
+
+    /// Returns the rowid after the change (undefined for deletes).
+    pub fn new_rowid(&self) -> i64 { self.new_rowid }
+
+    /// Returns the number of columns in the row being changed.
+    /// See http://www.sqlite.org/c3ref/preupdate_count.html
+    pub fn column_count(&self) -> int {
+        unsafe { sqlite3_preupdate_count(self.dbh) as int }
+    }
+
+    /// Returns 0 for a change made directly by a statement, 1 for a change
+    /// made by a trigger it fired, and so on.
+    /// See http://www.sqlite.org/c3ref/preupdate_count.html
+    pub fn depth(&self) -> int {
+        unsafe { sqlite3_preupdate_depth(self.dbh) as int }
+    }
+
+    /// Returns the value of column `i` before an update or delete.
+    /// See http://www.sqlite.org/c3ref/preupdate_count.html
+    pub fn old(&self, i: int) -> SqliteResult<BindArg> {
+        let mut v = ptr::mut_null();
+        try!(check(unsafe { sqlite3_preupdate_old(self.dbh, i as c_int, &mut v) }));
+        Ok(unsafe { value_to_bind_arg(v) })
+    }
+
+    /// Returns the value of column `i` after an insert or update.
+    /// See http://www.sqlite.org/c3ref/preupdate_count.html
+    pub fn new(&self, i: int) -> SqliteResult<BindArg> {
+        let mut v = ptr::mut_null();
+        try!(check(unsafe { sqlite3_preupdate_new(self.dbh, i as c_int, &mut v) }));
+        Ok(unsafe { value_to_bind_arg(v) })
+    }
+}
 
 /// The database connection.
+///
+/// A connection can be moved to another thread (it is `Send`). That is
+/// sound as long as `threading_mode()` is not `SingleThread`, which
+/// `open_with_flags` checks:
+///
+/// * in `MultiThread` mode SQLite takes no locks on a connection, so it must
+///   never be used by two threads at once. Rust guarantees that because a
+///   `Database` is not `Sync` and its cursors borrow it, so they move with
+///   it. `InterruptHandle` is the one exception, since `sqlite3_interrupt`
+///   may be called from any thread while the connection is in use.
+/// * in `Serialized` mode SQLite would also allow sharing, but the callbacks
+///   registered on a connection are not synchronized, so `Database` is not
+///   `Sync` either, even when opened with `SQLITE_OPEN_FULLMUTEX`.
+///
+/// Use a `pool::Pool` or one connection per thread to work from several
+/// threads.
 pub struct Database {
     dbh: *mut dbh,
+    // shared with every `InterruptHandle`; nulled out before closing
+    interrupt: Arc<Mutex<*mut dbh>>,
+    // callbacks registered with SQLite; boxed so their address stays put
+    progress_handler: RefCell<Option<(int, Box<ProgressHandler>)>>,
+    busy_handler: RefCell<Option<Box<BusyHandler>>>,
+    update_hook: RefCell<Option<Box<UpdateHook>>>,
+    commit_hook: RefCell<Option<Box<CommitHook>>>,
+    rollback_hook: RefCell<Option<Box<RollbackHook>>>,
+    wal_hook: RefCell<Option<Box<WalHook>>>,
+    trace: RefCell<Option<Box<TraceCallback>>>,
+    authorizer: RefCell<Option<Box<Authorizer>>>,
+    #[cfg(feature = "preupdate_hook")]
+    #[cfg(not(feature = "loadable_extension"))]
+    preupdate_hook: RefCell<Option<Box<PreUpdateHook>>>,
 }
 
 impl Drop for Database {
@@ -46,26 +204,88 @@ impl Drop for Database {
     /// See http://www.sqlite.org/c3ref/close.html
     fn drop(&mut self) {
         debug!("`Database.drop()`: dbh={:?}", self.dbh);
+        *self.interrupt.lock() = ptr::mut_null();
         let r = unsafe { sqlite3_close_v2(self.dbh) };
         assert_eq!(r, SQLITE_OK as i32)
     }
 }
 
+/// A handle that can interrupt the statements running on a connection from
+/// any thread. Once the connection is closed, `interrupt` does nothing.
+#[deriving(Clone)]
+pub struct InterruptHandle {
+    dbh: Arc<Mutex<*mut dbh>>,
+}
+
+impl InterruptHandle {
+    /// Causes any pending operation on the connection to stop at its earliest
+    /// opportunity with `SQLITE_INTERRUPT`.
+    /// See http://www.sqlite.org/c3ref/interrupt.html
+    pub fn interrupt(&self) {
+        let dbh = self.dbh.lock();
+        if !dbh.is_null() {
+            unsafe { sqlite3_interrupt(*dbh) }
+        }
+    }
+}
+
+struct RestoreProgressHandler<'db> {
+    database: &'db Database,
+    prev: Option<Option<(int, Box<ProgressHandler>)>>,
+}
+
+#[unsafe_destructor]
+impl<'db> Drop for RestoreProgressHandler<'db> {
+    fn drop(&mut self) {
+        let prev = self.prev.take().unwrap();
+        self.database.install_progress_handler(prev);
+    }
+}
+
+/// Keeps `sqlite3_load_extension` enabled for a connection, see
+/// `Database::enable_load_extension`.
+pub struct LoadExtensionGuard<'db> {
+    database: &'db Database,
+    was_enabled: bool,
+}
+
+#[unsafe_destructor]
+impl<'db> Drop for LoadExtensionGuard<'db> {
+    fn drop(&mut self) {
+        let _ = self.database.set_load_extension(self.was_enabled);
+    }
+}
+
 impl Database {
     /// Opens a new database connection.
     /// `path` can either be a filesystem path or ":memory:".
     /// See http://www.sqlite.org/c3ref/open.html
     pub fn new(path: &str) -> SqliteResult<Database> {
+        Database::open_with_flags(path, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE, None)
+    }
+
+    /// Opens a new database connection with the given flags, using the
+    /// named VFS or the default one. Fails with `SQLITE_MISUSE` if SQLite
+    /// was built in `SingleThread` mode, where moving a connection to
+    /// another thread would not be safe.
+    /// See http://www.sqlite.org/c3ref/open.html
+    pub fn open_with_flags(path: &str, flags: OpenFlags, vfs: Option<&str>) -> SqliteResult<Database> {
+        match threading_mode() {
+            SingleThread => return Err(SQLITE_MISUSE),
+            MultiThread | Serialized => {}
+        }
         let mut dbh = ptr::mut_null();
+        let vfs = vfs.map(|v| v.to_c_str());
         let r = path.with_c_str( |_path| {
             unsafe {
-                sqlite3_open(_path, &mut dbh)
+                sqlite3_open_v2(_path, &mut dbh, flags.bits() as c_int,
+                                vfs.as_ref().map_or(ptr::null(), |v| v.as_ptr()))
             }
         });
         match check(r) {
             Ok(()) => {
                 debug!("`open()`: dbh={:?}", dbh);
-                Ok(Database { dbh: dbh })
+                Ok(unsafe { Database::from_handle(dbh) })
             },
             Err(code) => {
                 unsafe {
@@ -76,6 +296,26 @@ impl Database {
         }
     }
 
+    /// Wraps a connection opened elsewhere, which is closed when the
+    /// `Database` is dropped.
+    #[allow(visible_private_types)]
+    pub unsafe fn from_handle(dbh: *mut dbh) -> Database {
+        Database {
+            dbh: dbh,
+            interrupt: Arc::new(Mutex::new(dbh)),
+            progress_handler: RefCell::new(None),
+            busy_handler: RefCell::new(None),
+            update_hook: RefCell::new(None),
+            commit_hook: RefCell::new(None),
+            rollback_hook: RefCell::new(None),
+            wal_hook: RefCell::new(None),
+            trace: RefCell::new(None),
+            authorizer: RefCell::new(None),
+            #[cfg(feature = "preupdate_hook")]
+            #[cfg(not(feature = "loadable_extension"))]
+            preupdate_hook: RefCell::new(None),
+        }
+    }
 
     /// Returns the error message of the the most recent call.
     /// See http://www.sqlite.org/c3ref/errcode.html
@@ -115,13 +355,44 @@ impl Database {
     /// Executes an SQL statement.
     /// See http://www.sqlite.org/c3ref/exec.html
     pub fn exec(&self, sql: &str) -> SqliteResult<()> {
+        self.exec_raw(sql, None, ptr::mut_null()).map_err(|e| e.kind)
+    }
+
+    /// Like `exec`, but on failure the error carries the message reported
+    /// by SQLite.
+    pub fn exec_with_message(&self, sql: &str) -> Result<(), SqliteError> {
+        self.exec_raw(sql, None, ptr::mut_null())
+    }
+
+    /// Executes an SQL statement, calling `cb` with the column names and
+    /// values of every result row. Returning `false` from `cb` aborts the
+    /// remaining statements, in which case the error is `SQLITE_ABORT`.
+    /// See http://www.sqlite.org/c3ref/exec.html
+    pub fn exec_with_callback(&self, sql: &str, cb: ExecCallback) -> SqliteResult<()> {
+        let mut cb = cb;
+        let arg = &mut cb as *mut ExecCallback as *mut c_void;
+        self.exec_raw(sql, Some(exec_callback), arg).map_err(|e| e.kind)
+    }
+
+    fn exec_raw(&self,
+                sql: &str,
+                cb: Option<extern "C" fn(*mut c_void, c_int, *mut *mut c_char, *mut *mut c_char) -> c_int>,
+                arg: *mut c_void) -> Result<(), SqliteError> {
+        let mut errmsg = ptr::mut_null();
         let r = sql.with_c_str( |_sql| {
             unsafe {
-                sqlite3_exec(self.dbh, _sql, ptr::mut_null(), ptr::mut_null(), ptr::mut_null())
+                sqlite3_exec(self.dbh, _sql, cb, arg, &mut errmsg)
             }
         });
 
-        check(r)
+        let errmsg = unsafe { take_errmsg(errmsg) };
+        match check(r) {
+            Ok(()) => Ok(()),
+            Err(code) => {
+                let desc = errmsg.unwrap_or_else(|| self.get_errmsg());
+                Err(SqliteError { kind: code, desc: desc })
+            }
+        }
     }
 
     /// Returns the number of modified/inserted/deleted rows by the most recent
@@ -141,12 +412,711 @@ impl Database {
         }
     }
 
-    /// Sets a busy timeout.
+    /// Returns whether the connection is in autocommit mode, i.e. not
+    /// inside an explicit transaction.
+    /// See http://www.sqlite.org/c3ref/get_autocommit.html
+    pub fn is_autocommit(&self) -> bool {
+        unsafe {
+            sqlite3_get_autocommit(self.dbh) != 0
+        }
+    }
+
+    /// Returns a handle that can interrupt this connection from another
+    /// thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle { dbh: self.interrupt.clone() }
+    }
+
+    /// Returns whether an interrupt is currently in effect for this
+    /// connection.
+    /// See http://www.sqlite.org/c3ref/interrupt.html
+    pub fn is_interrupted(&self) -> bool {
+        unsafe {
+            sqlite3_is_interrupted(self.dbh) != 0
+        }
+    }
+
+    /// Registers `handler` to be called every `n_ops` virtual machine
+    /// instructions while a statement runs. Returning `false` (or failing)
+    /// aborts the statement with `SQLITE_INTERRUPT`.
+    /// See http://www.sqlite.org/c3ref/progress_handler.html
+    pub fn set_progress_handler<F: FnMut<(), bool> + Send>(&self, n_ops: int, handler: F) {
+        self.install_progress_handler(Some((n_ops, box() (box handler as ProgressHandler))));
+    }
+
+    /// Removes the progress handler, if any.
+    pub fn clear_progress_handler(&self) {
+        self.install_progress_handler(None);
+    }
+
+    fn install_progress_handler(&self, handler: Option<(int, Box<ProgressHandler>)>)
+                                -> Option<(int, Box<ProgressHandler>)> {
+        let mut slot = self.progress_handler.borrow_mut();
+        unsafe {
+            match handler {
+                Some((n_ops, ref cb)) => {
+                    let arg = &**cb as *const ProgressHandler as *mut c_void;
+                    sqlite3_progress_handler(self.dbh, n_ops as c_int, Some(progress_callback), arg)
+                }
+                None => sqlite3_progress_handler(self.dbh, 0, None, ptr::mut_null())
+            }
+        }
+        mem::replace(&mut *slot, handler)
+    }
+
+    /// Runs `f`, aborting with `SQLITE_INTERRUPT` any statement that is
+    /// still running once `timeout` has elapsed. The previous progress
+    /// handler is restored afterwards.
+    pub fn with_deadline<T>(&self, timeout: Duration, f: || -> T) -> T {
+        let deadline = deadline_after(timeout);
+        let check = move |&mut:| time::precise_time_ns() < deadline;
+        let prev = self.install_progress_handler(Some((1000, box() (box check as ProgressHandler))));
+        let _restore = RestoreProgressHandler { database: self, prev: Some(prev) };
+        f()
+    }
+
+    /// Registers `hook` to be called with the operation, database name,
+    /// table name and rowid of every row inserted, updated or deleted.
+    /// See http://www.sqlite.org/c3ref/update_hook.html
+    pub fn set_update_hook<F: FnMut<(UpdateOperation, String, String, i64), ()> + Send>(&self, hook: F) {
+        let hook = box() (box hook as UpdateHook);
+        unsafe {
+            let arg = &*hook as *const UpdateHook as *mut c_void;
+            sqlite3_update_hook(self.dbh, Some(update_callback), arg);
+        }
+        *self.update_hook.borrow_mut() = Some(hook);
+    }
+
+    /// Removes the update hook, if any.
+    pub fn clear_update_hook(&self) {
+        unsafe { sqlite3_update_hook(self.dbh, None, ptr::mut_null()); }
+        *self.update_hook.borrow_mut() = None;
+    }
+
+    /// Registers `hook` to be called whenever a transaction is about to be
+    /// committed. Returning `false` (or failing) turns the commit into a
+    /// rollback, and the commit fails with `SQLITE_CONSTRAINT`.
+    /// See http://www.sqlite.org/c3ref/commit_hook.html
+    pub fn set_commit_hook<F: FnMut<(), bool> + Send>(&self, hook: F) {
+        let hook = box() (box hook as CommitHook);
+        unsafe {
+            let arg = &*hook as *const CommitHook as *mut c_void;
+            sqlite3_commit_hook(self.dbh, Some(commit_callback), arg);
+        }
+        *self.commit_hook.borrow_mut() = Some(hook);
+    }
+
+    /// Removes the commit hook, if any.
+    pub fn clear_commit_hook(&self) {
+        unsafe { sqlite3_commit_hook(self.dbh, None, ptr::mut_null()); }
+        *self.commit_hook.borrow_mut() = None;
+    }
+
+    /// Registers `hook` to be called whenever a transaction is rolled back.
+    /// See http://www.sqlite.org/c3ref/commit_hook.html
+    pub fn set_rollback_hook<F: FnMut<(), ()> + Send>(&self, hook: F) {
+        let hook = box() (box hook as RollbackHook);
+        unsafe {
+            let arg = &*hook as *const RollbackHook as *mut c_void;
+            sqlite3_rollback_hook(self.dbh, Some(rollback_callback), arg);
+        }
+        *self.rollback_hook.borrow_mut() = Some(hook);
+    }
+
+    /// Removes the rollback hook, if any.
+    pub fn clear_rollback_hook(&self) {
+        unsafe { sqlite3_rollback_hook(self.dbh, None, ptr::mut_null()); }
+        *self.rollback_hook.borrow_mut() = None;
+    }
+
+    /// Registers `hook` to be called with the database name and the number
+    /// of pages in the write-ahead log after each commit in WAL mode.
+    /// This replaces the automatic checkpointing SQLite does by default.
+    /// See http://www.sqlite.org/c3ref/wal_hook.html
+    pub fn set_wal_hook<F: FnMut<(String, int), ()> + Send>(&self, hook: F) {
+        let hook = box() (box hook as WalHook);
+        unsafe {
+            let arg = &*hook as *const WalHook as *mut c_void;
+            sqlite3_wal_hook(self.dbh, Some(wal_callback), arg);
+        }
+        *self.wal_hook.borrow_mut() = Some(hook);
+    }
+
+    /// Removes the WAL hook, if any.
+    pub fn clear_wal_hook(&self) {
+        unsafe { sqlite3_wal_hook(self.dbh, None, ptr::mut_null()); }
+        *self.wal_hook.borrow_mut() = None;
+    }
+
+    /// Registers `callback` to receive the events selected by `mask`.
+    /// See http://www.sqlite.org/c3ref/trace_v2.html
+    pub fn trace<F: FnMut<(TraceEvent,), ()> + Send>(&self, mask: TraceMask, callback: F) -> SqliteResult<()> {
+        let callback = box() (box callback as TraceCallback);
+        let r = unsafe {
+            let arg = &*callback as *const TraceCallback as *mut c_void;
+            sqlite3_trace_v2(self.dbh, mask.bits() as c_uint, Some(trace_callback), arg)
+        };
+        *self.trace.borrow_mut() = Some(callback);
+        check(r)
+    }
+
+    /// Routes the events selected by `mask` to the `log` crate: statements
+    /// and rows at debug level, profiles at info level.
+    pub fn trace_to_log(&self, mask: TraceMask) -> SqliteResult<()> {
+        self.trace(mask, move |&mut: event: TraceEvent| {
+            match event {
+                TraceStmt(sql) => debug!("sqlite trace: {}", sql),
+                TraceProfile(sql, ns) => info!("sqlite profile: {} ({}ns)", sql, ns),
+                TraceRow(sql) => debug!("sqlite row: {}", sql),
+                TraceClose => debug!("sqlite close"),
+            }
+        })
+    }
+
+    /// Stops tracing.
+    pub fn clear_trace(&self) -> SqliteResult<()> {
+        let r = unsafe {
+            sqlite3_trace_v2(self.dbh, 0, None, ptr::mut_null())
+        };
+        *self.trace.borrow_mut() = None;
+        check(r)
+    }
+
+    /// Registers `authorizer` to approve every action a statement will take
+    /// as it is compiled. Statements with a denied action fail to prepare
+    /// with `SQLITE_AUTH`; a failing callback counts as a denial.
+    /// See http://www.sqlite.org/c3ref/set_authorizer.html
+    pub fn set_authorizer<F: FnMut<(AuthContext,), Authorization> + Send>(&self, authorizer: F) -> SqliteResult<()> {
+        let authorizer = box() (box authorizer as Authorizer);
+        let r = unsafe {
+            let arg = &*authorizer as *const Authorizer as *mut c_void;
+            sqlite3_set_authorizer(self.dbh, Some(authorizer_callback), arg)
+        };
+        *self.authorizer.borrow_mut() = Some(authorizer);
+        check(r)
+    }
+
+    /// Installs a `ReadOnlyPolicy` allowing calls to `functions` only.
+    pub fn set_read_only_authorizer(&self, functions: &[&str]) -> SqliteResult<()> {
+        let policy = ReadOnlyPolicy::new(functions);
+        self.set_authorizer(move |&mut: ctx: AuthContext| policy.authorize(&ctx))
+    }
+
+    /// Removes the authorizer, if any.
+    pub fn clear_authorizer(&self) -> SqliteResult<()> {
+        let r = unsafe {
+            sqlite3_set_authorizer(self.dbh, None, ptr::mut_null())
+        };
+        *self.authorizer.borrow_mut() = None;
+        check(r)
+    }
+
+    /// Registers `hook` to be called with the old and new column values
+    /// just before each row is inserted, updated or deleted.
+    /// See http://www.sqlite.org/c3ref/preupdate_count.html
+    #[cfg(feature = "preupdate_hook")]
+    #[cfg(not(feature = "loadable_extension"))]
+    pub fn set_preupdate_hook<F: for<'a> FnMut<(&'a PreUpdate,), ()> + Send>(&self, hook: F) {
+        let hook = box() (box hook as PreUpdateHook);
+        unsafe {
+            let arg = &*hook as *const PreUpdateHook as *mut c_void;
+            sqlite3_preupdate_hook(self.dbh, Some(preupdate_callback), arg);
+        }
+        *self.preupdate_hook.borrow_mut() = Some(hook);
+    }
+
+    /// Removes the preupdate hook, if any.
+    #[cfg(feature = "preupdate_hook")]
+    #[cfg(not(feature = "loadable_extension"))]
+    pub fn clear_preupdate_hook(&self) {
+        unsafe { sqlite3_preupdate_hook(self.dbh, None, ptr::mut_null()); }
+        *self.preupdate_hook.borrow_mut() = None;
+    }
+
+    /// Returns the declaration of `column` in `table`, searching every
+    /// attached database if `schema` is `None`. Fails with `SQLITE_ERROR`
+    /// if there is no such column.
+    /// See http://www.sqlite.org/c3ref/table_column_metadata.html
+    #[cfg(feature = "column_metadata")]
+    pub fn get_table_column_metadata(&self, schema: Option<&str>, table: &str, column: &str)
+                                     -> SqliteResult<ColumnMetadata> {
+        let mut decl_type = ptr::null();
+        let mut collation = ptr::null();
+        let mut not_null = 0;
+        let mut primary_key = 0;
+        let mut autoinc = 0;
+        let schema = schema.map(|s| s.to_c_str());
+        let table = table.to_c_str();
+        let column = column.to_c_str();
+        let r = unsafe {
+            sqlite3_table_column_metadata(
+                self.dbh,
+                schema.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+                table.as_ptr(),
+                column.as_ptr(),
+                &mut decl_type, &mut collation,
+                &mut not_null, &mut primary_key, &mut autoinc)
+        };
+        try!(check(r));
+        unsafe {
+            Ok(ColumnMetadata {
+                decl_type: opt_string(decl_type),
+                collation: opt_string(collation).unwrap_or(String::new()),
+                not_null: not_null != 0,
+                primary_key: primary_key != 0,
+                autoincrement: autoinc != 0,
+            })
+        }
+    }
+
+    /// Copies the database `schema` of this connection over the "main"
+    /// database of `dest` in one pass.
+    /// See http://www.sqlite.org/c3ref/backup_finish.html
+    pub fn backup_to(&self, schema: &str, dest: &Database) -> SqliteResult<()> {
+        let b = "main".with_c_str(|_main| schema.with_c_str(|_schema| unsafe {
+            sqlite3_backup_init(dest.dbh, _main, self.dbh, _schema)
+        }));
+        if b.is_null() {
+            return check(unsafe { sqlite3_errcode(dest.dbh) });
+        }
+        let r = unsafe { sqlite3_backup_step(b, -1) };
+        let f = unsafe { sqlite3_backup_finish(b) };
+        if r != SQLITE_DONE as c_int {
+            try!(check(r));
+        }
+        check(f)
+    }
+
+    /// Allows `load_extension` until the returned guard is dropped, when
+    /// the previous setting is restored. The SQL function
+    /// `load_extension()` stays disabled.
+    /// See http://www.sqlite.org/c3ref/c_dbconfig_defensive.html
+    pub fn enable_load_extension<'db>(&'db self) -> SqliteResult<LoadExtensionGuard<'db>> {
+        let was_enabled = try!(self.set_load_extension(true));
+        Ok(LoadExtensionGuard { database: self, was_enabled: was_enabled })
+    }
+
+    /// Sets whether extensions can be loaded through the C API, returning
+    /// the previous setting.
+    fn set_load_extension(&self, enabled: bool) -> SqliteResult<bool> {
+        let mut was = -1 as c_int;
+        // Passing a negative value only reads the setting.
+        try!(check(unsafe {
+            sqlite3_db_config(self.dbh, SQLITE_DBCONFIG_ENABLE_LOAD_EXTENSION, -1 as c_int, &mut was as *mut c_int)
+        }));
+        let mut now = 0 as c_int;
+        try!(check(unsafe {
+            sqlite3_db_config(self.dbh, SQLITE_DBCONFIG_ENABLE_LOAD_EXTENSION, enabled as c_int, &mut now as *mut c_int)
+        }));
+        Ok(was != 0)
+    }
+
+    /// Loads the extension in the shared library at `path`, calling
+    /// `entry_point`, or the name SQLite derives from the file name if
+    /// `None`. Loading is only enabled for the duration of the call. On
+    /// failure the error carries the message reported by SQLite or the
+    /// extension.
+    /// See http://www.sqlite.org/c3ref/load_extension.html
+    pub fn load_extension(&self, path: &str, entry_point: Option<&str>) -> Result<(), SqliteError> {
+        let _enabled = try!(self.enable_load_extension().map_err(|e| {
+            SqliteError { kind: e, desc: self.get_errmsg() }
+        }));
+        let mut errmsg = ptr::mut_null();
+        let entry_point = entry_point.map(|e| e.to_c_str());
+        let r = path.with_c_str(|_path| unsafe {
+            sqlite3_load_extension(self.dbh, _path,
+                                   entry_point.as_ref().map_or(ptr::null(), |e| e.as_ptr()),
+                                   &mut errmsg)
+        });
+        let errmsg = unsafe { take_errmsg(errmsg) };
+        match check(r) {
+            Ok(()) => Ok(()),
+            Err(code) => {
+                let desc = errmsg.unwrap_or_else(|| self.get_errmsg());
+                Err(SqliteError { kind: code, desc: desc })
+            }
+        }
+    }
+
+    /// Starts a session recording the changes made through this connection
+    /// to database `schema`. Call `Session::attach` to choose the tables.
+    /// See http://www.sqlite.org/session/sqlite3session_create.html
+    #[cfg(feature = "session")]
+    #[cfg(not(feature = "loadable_extension"))]
+    pub fn session<'db>(&'db self, schema: &str) -> SqliteResult<Session<'db>> {
+        Session::new(&self.dbh, schema)
+    }
+
+    /// Applies `changeset` (or a patchset) in one transaction, asking
+    /// `on_conflict` what to do about each change that conflicts with the
+    /// current contents. Fails with `SQLITE_ABORT` if it says to abort, and
+    /// with `SQLITE_MISUSE` if it says to replace where that is not allowed.
+    /// See http://www.sqlite.org/session/sqlite3changeset_apply.html
+    #[cfg(feature = "session")]
+    #[cfg(not(feature = "loadable_extension"))]
+    pub fn apply_changeset(&self, changeset: &Changeset, on_conflict: ConflictHandler) -> SqliteResult<()> {
+        changeset.apply(&self.dbh, on_conflict)
+    }
+
+    /// Returns a copy of the database `schema`, as it would be stored on
+    /// disk. Fails with `SQLITE_ERROR` if there is no such database.
+    /// See http://www.sqlite.org/c3ref/serialize.html
+    pub fn serialize(&self, schema: &str) -> SqliteResult<Vec<u8>> {
+        let mut size = 0i64;
+        let p = schema.with_c_str(|_schema| unsafe {
+            sqlite3_serialize(self.dbh, _schema, &mut size, 0)
+        });
+        if p.is_null() {
+            return Err(SQLITE_ERROR);
+        }
+        let bytes = unsafe { slice::raw::buf_as_slice(p as *const u8, size as uint, |b| Vec::from_slice(b)) };
+        unsafe { sqlite3_free(p as *mut c_void) };
+        Ok(bytes)
+    }
+
+    /// Like `serialize`, but returns SQLite's own memory instead of a
+    /// copy. This is only possible for databases held in one contiguous
+    /// buffer, such as those opened with `from_bytes`; otherwise returns
+    /// `None`. The connection is borrowed mutably so that it cannot change
+    /// the buffer while the slice is alive.
+    pub fn serialize_no_copy<'a>(&'a mut self, schema: &str) -> Option<&'a [u8]> {
+        let mut size = 0i64;
+        let p = schema.with_c_str(|_schema| unsafe {
+            sqlite3_serialize(self.dbh, _schema, &mut size, SQLITE_SERIALIZE_NOCOPY)
+        });
+        if p.is_null() {
+            return None;
+        }
+        Some(unsafe { mem::transmute(raw::Slice { data: p as *const u8, len: size as uint }) })
+    }
+
+    /// Opens an in-memory connection whose "main" database is a copy of
+    /// `bytes`, such as the output of `serialize`. Unless `read_only`, the
+    /// database can be written to and grow.
+    /// See http://www.sqlite.org/c3ref/deserialize.html
+    pub fn from_bytes(bytes: &[u8], read_only: bool) -> SqliteResult<Database> {
+        let database = try!(Database::new(":memory:"));
+        // The copy belongs to SQLite from here on: it is freed when the
+        // connection closes, or right away if deserializing fails.
+        let buf = unsafe { sqlite3_malloc64(bytes.len() as u64) as *mut u8 };
+        if buf.is_null() && bytes.len() > 0 {
+            return Err(SQLITE_NOMEM);
+        }
+        unsafe { ptr::copy_nonoverlapping_memory(buf, bytes.as_ptr(), bytes.len()) };
+        let flags = SQLITE_DESERIALIZE_FREEONCLOSE |
+            if read_only { SQLITE_DESERIALIZE_READONLY } else { SQLITE_DESERIALIZE_RESIZEABLE };
+        let r = "main".with_c_str(|_main| unsafe {
+            sqlite3_deserialize(database.dbh, _main, buf, bytes.len() as i64, bytes.len() as i64, flags)
+        });
+        try!(check(r));
+        Ok(database)
+    }
+
+    /// Checkpoints the write-ahead log of `schema` (every attached database
+    /// if `None`). Returns the number of frames in the log and the number
+    /// of those that were checkpointed.
+    /// See http://www.sqlite.org/c3ref/wal_checkpoint_v2.html
+    pub fn wal_checkpoint(&self, schema: Option<&str>, mode: CheckpointMode) -> SqliteResult<(int, int)> {
+        let mut log = 0;
+        let mut ckpt = 0;
+        let r = match schema {
+            Some(schema) => schema.with_c_str(|_schema| unsafe {
+                sqlite3_wal_checkpoint_v2(self.dbh, _schema, mode as c_int, &mut log, &mut ckpt)
+            }),
+            None => unsafe {
+                sqlite3_wal_checkpoint_v2(self.dbh, ptr::null(), mode as c_int, &mut log, &mut ckpt)
+            }
+        };
+        try!(check(r));
+        Ok((log as int, ckpt as int))
+    }
+
+    /// Makes every commit that leaves at least `pages` frames in the
+    /// write-ahead log run a passive checkpoint. Zero or a negative value
+    /// disables automatic checkpoints. Replaces any WAL hook.
+    /// See http://www.sqlite.org/c3ref/wal_autocheckpoint.html
+    pub fn wal_autocheckpoint(&self, pages: int) -> SqliteResult<()> {
+        let r = unsafe {
+            sqlite3_wal_autocheckpoint(self.dbh, pages as c_int)
+        };
+        *self.wal_hook.borrow_mut() = None;
+        check(r)
+    }
+
+    /// Sets a busy timeout, replacing any busy handler. Negative timeouts
+    /// act as zero and ones past `i32::MAX` milliseconds are clamped.
     /// See http://www.sqlite.org/c3ref/busy_timeout.html
-    pub fn set_busy_timeout(&self, ms: int) {
+    pub fn set_busy_timeout(&self, timeout: Duration) -> SqliteResult<()> {
+        let ms = cmp::min(cmp::max(timeout.num_milliseconds(), 0), i32::MAX as i64);
         let r = unsafe {
             sqlite3_busy_timeout(self.dbh, ms as c_int)
         };
-        assert_eq!(r, SQLITE_OK as c_int);
+        *self.busy_handler.borrow_mut() = None;
+        check(r)
+    }
+
+    /// Registers `handler` to be called when a table is locked. It receives
+    /// the number of times it has been called for the current lock and
+    /// returns `true` to retry or `false` to give up with `SQLITE_BUSY`.
+    /// Replaces any busy timeout.
+    /// See http://www.sqlite.org/c3ref/busy_handler.html
+    pub fn set_busy_handler<F: FnMut<(int,), bool> + Send>(&self, handler: F) -> SqliteResult<()> {
+        let handler = box() (box handler as BusyHandler);
+        let arg = &*handler as *const BusyHandler as *mut c_void;
+        let r = unsafe {
+            sqlite3_busy_handler(self.dbh, Some(busy_callback), arg)
+        };
+        *self.busy_handler.borrow_mut() = Some(handler);
+        check(r)
+    }
+
+    /// Removes the busy handler or busy timeout, so that locked tables
+    /// fail immediately with `SQLITE_BUSY`.
+    pub fn clear_busy_handler(&self) -> SqliteResult<()> {
+        let r = unsafe {
+            sqlite3_busy_handler(self.dbh, None, ptr::mut_null())
+        };
+        *self.busy_handler.borrow_mut() = None;
+        check(r)
+    }
+
+    /// Registers `f` as the SQL function `name` taking `n_args` arguments,
+    /// or any number of them if `n_args` is -1. An `Err` from `f` fails the
+    /// statement with its `kind` and `desc`. SQLite may evaluate
+    /// `deterministic` functions once per statement and use them in
+    /// indexes. The function stays registered until it is replaced or the
+    /// connection is closed.
+    /// See http://www.sqlite.org/c3ref/create_function.html
+    pub fn create_function<F: for<'a> FnMut<(&'a [BindArg],), Result<BindArg, SqliteError>> + Send>(
+        &self, name: &str, n_args: int, deterministic: bool, f: F) -> SqliteResult<()> {
+        let flags = SQLITE_UTF8 | if deterministic { SQLITE_DETERMINISTIC } else { 0 };
+        let r = name.with_c_str(|_name| unsafe {
+            // SQLite drops the function through `drop_function`, even if
+            // registering it fails.
+            let arg: *mut c_void = mem::transmute(box() (box f as ScalarFunction));
+            sqlite3_create_function_v2(self.dbh, _name, n_args as c_int, flags, arg,
+                                       Some(function_callback), None, None, Some(drop_function))
+        });
+        check(r)
+    }
+
+    /// Registers `f` as the collating sequence `name`, for use in
+    /// `COLLATE` clauses. Text that is not valid UTF-8 is compared after
+    /// replacing the invalid bytes.
+    /// See http://www.sqlite.org/c3ref/create_collation.html
+    pub fn create_collation<F: for<'a, 'b> FnMut<(&'a str, &'b str), Ordering> + Send>(
+        &self, name: &str, f: F) -> SqliteResult<()> {
+        let r = name.with_c_str(|_name| unsafe {
+            let arg: *mut c_void = mem::transmute(box() (box f as Collation));
+            let r = sqlite3_create_collation_v2(self.dbh, _name, SQLITE_UTF8, arg,
+                                                Some(collation_callback), Some(drop_collation));
+            // Unlike `sqlite3_create_function_v2`, this does not call the
+            // destructor when it fails.
+            if r != SQLITE_OK as c_int {
+                drop_collation(arg);
+            }
+            r
+        });
+        check(r)
+    }
+
+    /// Registers `module` as the virtual table module `name`, for use in
+    /// `CREATE VIRTUAL TABLE ... USING name`.
+    /// See http://www.sqlite.org/vtab.html
+    pub fn create_module<M: Module + Send>(&self, name: &str, module: M) -> SqliteResult<()> {
+        vtab::create_module(&self.dbh, name, box module as Box<Module + Send>)
+    }
+}
+
+extern "C" fn exec_callback(arg: *mut c_void,
+                            n: c_int,
+                            values: *mut *mut c_char,
+                            names: *mut *mut c_char) -> c_int {
+    unsafe {
+        let cb: &mut ExecCallback = mem::transmute(arg);
+        let n = n as uint;
+        let names = slice::raw::buf_as_slice(names as *const *mut c_char, n, |names| {
+            names.iter().map(|&p| string::raw::from_buf(p as *const u8)).collect::<Vec<String>>()
+        });
+        let values = slice::raw::buf_as_slice(values as *const *mut c_char, n, |values| {
+            values.iter().map(|&p| opt_string(p as *const c_char)).collect::<Vec<Option<String>>>()
+        });
+        if catch_panic(false, || (*cb)(names.as_slice(), values.as_slice())) { 0 } else { 1 }
+    }
+}
+
+extern "C" fn progress_callback(arg: *mut c_void) -> c_int {
+    unsafe {
+        let cb: &mut ProgressHandler = mem::transmute(arg);
+        if catch_panic(false, || cb.call_mut(())) { 0 } else { 1 }
+    }
+}
+
+extern "C" fn busy_callback(arg: *mut c_void, count: c_int) -> c_int {
+    unsafe {
+        let cb: &mut BusyHandler = mem::transmute(arg);
+        catch_panic(false, || cb.call_mut((count as int,))) as c_int
+    }
+}
+
+extern "C" fn update_callback(arg: *mut c_void,
+                              op: c_int,
+                              db_name: *const c_char,
+                              table_name: *const c_char,
+                              rowid: i64) {
+    unsafe {
+        let cb: &mut UpdateHook = mem::transmute(arg);
+        let op = match from_uint::<UpdateOperation>(op as uint) {
+            Some(op) => op,
+            None => return
+        };
+        let db_name = string::raw::from_buf(db_name as *const u8);
+        let table_name = string::raw::from_buf(table_name as *const u8);
+        catch_panic((), || cb.call_mut((op, db_name, table_name, rowid)));
+    }
+}
+
+extern "C" fn commit_callback(arg: *mut c_void) -> c_int {
+    unsafe {
+        let cb: &mut CommitHook = mem::transmute(arg);
+        if catch_panic(false, || cb.call_mut(())) { 0 } else { 1 }
+    }
+}
+
+extern "C" fn rollback_callback(arg: *mut c_void) {
+    unsafe {
+        let cb: &mut RollbackHook = mem::transmute(arg);
+        catch_panic((), || cb.call_mut(()));
     }
 }
+
+extern "C" fn wal_callback(arg: *mut c_void, _dbh: *mut dbh, db_name: *const c_char, pages: c_int) -> c_int {
+    unsafe {
+        let cb: &mut WalHook = mem::transmute(arg);
+        let db_name = string::raw::from_buf(db_name as *const u8);
+        catch_panic((), || cb.call_mut((db_name, pages as int)));
+    }
+    SQLITE_OK as c_int
+}
+
+#[cfg(feature = "preupdate_hook")]
+#[cfg(not(feature = "loadable_extension"))]
+extern "C" fn preupdate_callback(arg: *mut c_void,
+                                 dbh: *mut dbh,
+                                 op: c_int,
+                                 db_name: *const c_char,
+                                 table_name: *const c_char,
+                                 old_rowid: i64,
+                                 new_rowid: i64) {
+    unsafe {
+        let cb: &mut PreUpdateHook = mem::transmute(arg);
+        let op = match from_uint::<UpdateOperation>(op as uint) {
+            Some(op) => op,
+            None => return
+        };
+        let change = PreUpdate {
+            dbh: dbh,
+            op: op,
+            db_name: string::raw::from_buf(db_name as *const u8),
+            table_name: string::raw::from_buf(table_name as *const u8),
+            old_rowid: old_rowid,
+            new_rowid: new_rowid,
+        };
+        catch_panic((), || cb.call_mut((&change,)));
+    }
+}
+
+extern "C" fn trace_callback(mask: c_uint, arg: *mut c_void, p: *mut c_void, x: *mut c_void) -> c_int {
+    unsafe {
+        let cb: &mut TraceCallback = mem::transmute(arg);
+        let sql = |sth: *mut stmt| opt_string(sqlite3_sql(sth)).unwrap_or(String::new());
+        let mask = mask as u32;
+        let event = if mask == SQLITE_TRACE_STMT.bits() {
+            let expanded = sqlite3_expanded_sql(p as *mut stmt);
+            if expanded.is_null() {
+                TraceStmt(opt_string(x as *const c_char).unwrap_or(String::new()))
+            } else {
+                let s = string::raw::from_buf(expanded as *const u8);
+                sqlite3_free(expanded as *mut c_void);
+                TraceStmt(s)
+            }
+        } else if mask == SQLITE_TRACE_PROFILE.bits() {
+            TraceProfile(sql(p as *mut stmt), *(x as *const i64))
+        } else if mask == SQLITE_TRACE_ROW.bits() {
+            TraceRow(sql(p as *mut stmt))
+        } else if mask == SQLITE_TRACE_CLOSE.bits() {
+            TraceClose
+        } else {
+            return 0
+        };
+        catch_panic((), || cb.call_mut((event.clone(),)));
+    }
+    0
+}
+
+extern "C" fn authorizer_callback(arg: *mut c_void,
+                                  code: c_int,
+                                  arg1: *const c_char,
+                                  arg2: *const c_char,
+                                  db_name: *const c_char,
+                                  accessor: *const c_char) -> c_int {
+    unsafe {
+        let cb: &mut Authorizer = mem::transmute(arg);
+        let ctx = AuthContext {
+            action: AuthAction::from_code(code as int, opt_string(arg1), opt_string(arg2)),
+            database_name: opt_string(db_name),
+            accessor: opt_string(accessor),
+        };
+        catch_panic(AuthDeny, || cb.call_mut((ctx.clone(),))) as c_int
+    }
+}
+
+extern "C" fn function_callback(ctx: *mut context, argc: c_int, argv: *mut *mut value) {
+    unsafe {
+        let cb: &mut ScalarFunction = mem::transmute(sqlite3_user_data(ctx));
+        let args = slice::raw::buf_as_slice(argv as *const *mut value, argc as uint, |values| {
+            values.iter().map(|&v| value_to_bind_arg(v)).collect::<Vec<BindArg>>()
+        });
+        let panicked = Err(SqliteError { kind: SQLITE_ERROR, desc: "function panicked".to_string() });
+        match catch_panic(panicked, || cb.call_mut((args.as_slice(),))) {
+            Ok(v) => set_result(ctx, &v),
+            Err(e) => {
+                sqlite3_result_error(ctx, e.desc.as_ptr() as *const c_char, e.desc.len() as c_int);
+                sqlite3_result_error_code(ctx, e.kind as c_int);
+            }
+        }
+    }
+}
+
+extern "C" fn drop_function(arg: *mut c_void) {
+    let _: Box<ScalarFunction> = unsafe { mem::transmute(arg) };
+}
+
+extern "C" fn collation_callback(arg: *mut c_void,
+                                 len_a: c_int,
+                                 a: *const c_void,
+                                 len_b: c_int,
+                                 b: *const c_void) -> c_int {
+    unsafe {
+        let cb: &mut Collation = mem::transmute(arg);
+        let text = |p: *const c_void, len: c_int| {
+            if p.is_null() || len <= 0 {
+                String::new()
+            } else {
+                slice::raw::buf_as_slice(p as *const u8, len as uint, |bytes| {
+                    String::from_utf8_lossy(bytes).into_string()
+                })
+            }
+        };
+        let (a, b) = (text(a, len_a), text(b, len_b));
+        match catch_panic(Equal, || cb.call_mut((a.as_slice(), b.as_slice()))) {
+            Less => -1,
+            Equal => 0,
+            Greater => 1,
+        }
+    }
+}
+
+extern "C" fn drop_collation(arg: *mut c_void) {
+    let _: Box<Collation> = unsafe { mem::transmute(arg) };
+}
@@ -0,0 +1,165 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Writing loadable SQLite extensions with this crate.
+//!
+//! `sqlite3_extension_init!` defines the entry point SQLite calls when the
+//! extension is loaded, and runs an initialization function with the
+//! loading connection wrapped in a `Database`:
+//!
+//! ```ignore
+//! fn init(database: &Database) -> Result<(), SqliteError> {
+//!     database.set_read_only_authorizer(&[])
+//!             .map_err(|e| SqliteError { kind: e, desc: "authorizer".to_string() })
+//! }
+//! sqlite3_extension_init!(sqlite3_myext_init, init)
+//! ```
+//!
+//! Build the extension as a dynamic library with the `loadable_extension`
+//! feature. The crate then does not link `libsqlite3` but calls SQLite
+//! through the `sqlite3_api_routines` table the host passes to the entry
+//! point, so the extension works in hosts that link SQLite statically,
+//! such as the stock `sqlite3` shell, and exports nothing but its entry
+//! point. That table has no entries for the functions behind the
+//! `preupdate_hook` and `session` features, so the APIs they add are left
+//! out when building an extension.
+//!
+//! Anything the initialization function registers on the connection
+//! stays registered after it returns: SQL functions, collations and
+//! virtual table modules (`create_function`, `create_collation`,
+//! `create_module`) as well as hooks, an authorizer or a trace callback.
+//! See http://www.sqlite.org/loadext.html
+
+use database::Database;
+use std::mem;
+use types::*;
+use util::*;
+
+/// Defines `$name` as the entry point of a loadable extension that runs
+/// `$init`, a `fn(&Database) -> Result<(), SqliteError>`, on the loading
+/// connection. An error's `desc` is reported to whoever loaded the
+/// extension.
+#[macro_export]
+macro_rules! sqlite3_extension_init(
+    ($name:ident, $init:expr) => (
+        #[no_mangle]
+        pub extern "C" fn $name(db: *mut u8, err: *mut *mut u8, api: *const u8) -> i32 {
+            unsafe { ::sqlite3::extension::init_extension(db, err, api, $init) }
+        }
+    )
+)
+
+/// Called by the entry points `sqlite3_extension_init!` defines.
+#[doc(hidden)]
+pub unsafe fn init_extension(db: *mut u8,
+                             err: *mut *mut u8,
+                             api: *const u8,
+                             init: fn(&Database) -> Result<(), SqliteError>) -> i32 {
+    // Until the routines are set we cannot even allocate an error message.
+    match set_routines(api) {
+        Ok(()) => {}
+        Err(e) => return e as i32,
+    }
+    let database = Database::from_handle(mem::transmute(db));
+    let r = catch_panic(Err(SqliteError { kind: SQLITE_ERROR, desc: "extension panicked".to_string() }),
+                        || init(&database));
+    // The connection belongs to whoever loaded us; keep it open and keep
+    // whatever `init` registered on it.
+    mem::forget(database);
+    match r {
+        Ok(()) => SQLITE_OK as i32,
+        Err(e) => {
+            if !err.is_null() {
+                *err = copy_message(e.desc.as_slice()) as *mut u8;
+            }
+            e.kind as i32
+        }
+    }
+}
+
+/// Without `loadable_extension` the crate calls the SQLite it links.
+#[cfg(not(feature = "loadable_extension"))]
+unsafe fn set_routines(_api: *const u8) -> SqliteResult<()> {
+    Ok(())
+}
+
+#[cfg(feature = "loadable_extension")]
+use api_routines::set_routines;
+
+
+#[cfg(test)]
+mod tests {
+    use database::Database;
+    use ffi::{sqlite3_open_v2, sqlite3_free};
+    use libc::c_void;
+    use std::ptr;
+    use std::string;
+    use types::*;
+
+    fn init_ok(database: &Database) -> Result<(), SqliteError> {
+        database.exec_with_message("PRAGMA user_version = 7")
+    }
+
+    fn init_err(_: &Database) -> Result<(), SqliteError> {
+        Err(SqliteError { kind: SQLITE_ERROR, desc: "not today".to_string() })
+    }
+
+    sqlite3_extension_init!(sqlite3_testok_init, init_ok)
+    sqlite3_extension_init!(sqlite3_testerr_init, init_err)
+
+    /// Calls the entry points the way SQLite does when loading an
+    /// extension into the connection `db`.
+    #[test]
+    fn entry_points() {
+        let mut raw = ptr::mut_null();
+        let flags = SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE;
+        let r = ":memory:".with_c_str(|path| unsafe {
+            sqlite3_open_v2(path, &mut raw, flags.bits(), ptr::null())
+        });
+        assert_eq!(r, SQLITE_OK as i32);
+        let db = raw as *mut u8;
+
+        let mut err = ptr::mut_null();
+        assert_eq!(sqlite3_testok_init(db, &mut err, ptr::null()), SQLITE_OK as i32);
+        assert!(err.is_null());
+        assert_eq!(sqlite3_testerr_init(db, &mut err, ptr::null()), SQLITE_ERROR as i32);
+        assert!(!err.is_null());
+        unsafe {
+            assert_eq!(string::raw::from_buf(err as *const u8).as_slice(), "not today");
+            sqlite3_free(err as *mut c_void);
+        }
+
+        let database = unsafe { Database::from_handle(raw) };
+        let cursor = database.prepare("PRAGMA user_version").unwrap();
+        assert_eq!(cursor.step(), Ok(SQLITE_ROW));
+        assert_eq!(cursor.get_int(0), 7);
+    }
+}
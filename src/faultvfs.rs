@@ -0,0 +1,532 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! A VFS wrapper that injects I/O failures, for testing how an
+//! application copes with errors, full disks and power loss.
+//!
+//! `FaultVfs::new` wraps another `Vfs` and returns a `FaultController`
+//! that scripts failures and records an I/O trace while the wrapped VFS
+//! is registered and used through `Database`.
+//!
+//! Power loss is simulated by snapshotting each file before its first
+//! write since the last sync and restoring the snapshots when the power
+//! goes out, so it is only suited to small test databases. Deleting a
+//! file is treated as durable.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use types::*;
+use vfs::*;
+
+/// The kinds of I/O a `FaultVfs` counts, traces and can fail.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum IoOp {
+    IoOpen,
+    IoDelete,
+    IoRead,
+    IoWrite,
+    IoTruncate,
+    IoSync,
+}
+
+/// What happens when a scripted fault fires.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum Fault {
+    /// The operation fails with this error, e.g. `SQLITE_IOERR` or
+    /// `SQLITE_FULL` for a full disk.
+    FaultError(ResultError),
+    /// Writes since each file's last sync are lost, and every operation
+    /// fails with `SQLITE_IOERR` until `FaultController::power_on`.
+    FaultPowerLoss,
+}
+
+/// One traced operation. `offset` and `len` are zero where they don't
+/// apply.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub struct IoEvent {
+    pub op: IoOp,
+    pub file: Option<String>,
+    pub offset: u64,
+    pub len: uint,
+    pub result: VfsResult<()>,
+}
+
+struct Rule {
+    op: IoOp,
+    remaining: uint,
+    fault: Fault,
+}
+
+struct FaultState {
+    rules: Vec<Rule>,
+    counts: Vec<(IoOp, uint)>,
+    powered_off: bool,
+    trace: Option<Vec<IoEvent>>,
+    snapshots: HashMap<String, Vec<u8>>,
+}
+
+struct Shared {
+    inner: Box<Vfs + Send + Sync>,
+    state: Mutex<FaultState>,
+}
+
+/// A `Vfs` that forwards to another one, failing where it has been told to.
+pub struct FaultVfs {
+    shared: Arc<Shared>,
+}
+
+/// Scripts the failures of a `FaultVfs` and reads back what it did.
+#[deriving(Clone)]
+pub struct FaultController {
+    shared: Arc<Shared>,
+}
+
+struct FaultFile {
+    name: Option<String>,
+    inner: Box<VfsFile + Send>,
+    shared: Arc<Shared>,
+}
+
+impl FaultVfs {
+    pub fn new(inner: Box<Vfs + Send + Sync>) -> (FaultVfs, FaultController) {
+        let shared = Arc::new(Shared {
+            inner: inner,
+            state: Mutex::new(FaultState {
+                rules: Vec::new(),
+                counts: Vec::new(),
+                powered_off: false,
+                trace: None,
+                snapshots: HashMap::new(),
+            }),
+        });
+        (FaultVfs { shared: shared.clone() }, FaultController { shared: shared })
+    }
+}
+
+impl FaultController {
+    /// Makes the `n`th `op` from now on (counting from 1) fail with `fault`.
+    pub fn fail_nth(&self, op: IoOp, n: uint, fault: Fault) {
+        assert!(n > 0);
+        self.shared.state.lock().rules.push(Rule { op: op, remaining: n, fault: fault });
+    }
+
+    /// Forgets the scripted faults that have not fired yet.
+    pub fn clear_faults(&self) {
+        self.shared.state.lock().rules.clear();
+    }
+
+    /// Returns how many scripted faults have not fired yet.
+    pub fn pending_faults(&self) -> uint {
+        self.shared.state.lock().rules.len()
+    }
+
+    /// Returns how many times `op` has been attempted.
+    pub fn count(&self, op: IoOp) -> uint {
+        let state = self.shared.state.lock();
+        state.counts.iter().find(|&&(ref o, _)| *o == op).map(|&(_, n)| n).unwrap_or(0)
+    }
+
+    /// Cuts the power now.
+    pub fn power_loss(&self) {
+        let mut state = self.shared.state.lock();
+        cut_power(&*self.shared.inner, &mut *state);
+    }
+
+    /// Lets operations succeed again after a power loss. Connections that
+    /// were open when the power went out should be closed and reopened.
+    pub fn power_on(&self) {
+        self.shared.state.lock().powered_off = false;
+    }
+
+    /// Starts recording operations, discarding any earlier trace.
+    pub fn start_trace(&self) {
+        self.shared.state.lock().trace = Some(Vec::new());
+    }
+
+    /// Stops recording and returns what was recorded.
+    pub fn take_trace(&self) -> Vec<IoEvent> {
+        self.shared.state.lock().trace.take().unwrap_or(Vec::new())
+    }
+}
+
+fn cut_power(inner: &Vfs, state: &mut FaultState) {
+    state.powered_off = true;
+    for (name, snapshot) in state.snapshots.iter() {
+        let restored = inner.open(Some(name.as_slice()), SQLITE_OPEN_READWRITE).and_then(|mut file| {
+            try!(file.truncate(snapshot.len() as u64));
+            file.write(snapshot.as_slice(), 0)
+        });
+        if restored.is_err() {
+            warn!("could not discard unsynced writes to {}", name);
+        }
+    }
+    state.snapshots.clear();
+}
+
+impl Shared {
+    /// Decides whether `op` may go ahead, firing any fault that is due.
+    fn inject(&self, op: IoOp) -> VfsResult<()> {
+        let mut state = self.state.lock();
+        let mut bumped = false;
+        for entry in state.counts.mut_iter() {
+            let (ref o, ref mut n) = *entry;
+            if *o == op {
+                *n += 1;
+                bumped = true;
+            }
+        }
+        if !bumped {
+            state.counts.push((op.clone(), 1));
+        }
+        if state.powered_off {
+            return Err(SQLITE_IOERR);
+        }
+
+        let mut fired = None;
+        for rule in state.rules.mut_iter() {
+            if rule.op == op {
+                rule.remaining -= 1;
+                if rule.remaining == 0 && fired.is_none() {
+                    fired = Some(rule.fault.clone());
+                }
+            }
+        }
+        state.rules.retain(|rule| rule.remaining > 0);
+        match fired {
+            None => Ok(()),
+            Some(FaultError(e)) => Err(e),
+            Some(FaultPowerLoss) => {
+                cut_power(&*self.inner, &mut *state);
+                Err(SQLITE_IOERR)
+            }
+        }
+    }
+
+    fn record(&self, op: IoOp, file: &Option<String>, offset: u64, len: uint, result: VfsResult<()>) {
+        let mut state = self.state.lock();
+        match state.trace {
+            Some(ref mut trace) => trace.push(IoEvent {
+                op: op,
+                file: file.clone(),
+                offset: offset,
+                len: len,
+                result: result,
+            }),
+            None => {}
+        }
+    }
+}
+
+impl Vfs for FaultVfs {
+    fn open(&self, name: Option<&str>, flags: OpenFlags) -> VfsResult<Box<VfsFile + Send>> {
+        let name = name.map(|n| n.to_string());
+        let r = self.shared.inject(IoOpen).and_then(|()| {
+            self.shared.inner.open(name.as_ref().map(|n| n.as_slice()), flags)
+        });
+        self.shared.record(IoOpen, &name, 0, 0, r.as_ref().map(|_| ()).map_err(|e| *e));
+        let inner = try!(r);
+        Ok(box FaultFile {
+            name: name,
+            inner: inner,
+            shared: self.shared.clone(),
+        } as Box<VfsFile + Send>)
+    }
+
+    fn delete(&self, name: &str, sync_dir: bool) -> VfsResult<()> {
+        let r = self.shared.inject(IoDelete).and_then(|()| self.shared.inner.delete(name, sync_dir));
+        if r.is_ok() {
+            self.shared.state.lock().snapshots.remove(&name.to_string());
+        }
+        self.shared.record(IoDelete, &Some(name.to_string()), 0, 0, r.clone());
+        r
+    }
+
+    fn access(&self, name: &str, check: AccessCheck) -> VfsResult<bool> {
+        self.shared.inner.access(name, check)
+    }
+
+    fn full_pathname(&self, name: &str) -> VfsResult<String> {
+        self.shared.inner.full_pathname(name)
+    }
+}
+
+impl FaultFile {
+    /// Saves the file's contents before its first change since the last
+    /// sync, so that power loss can put them back.
+    fn snapshot(&mut self) -> VfsResult<()> {
+        let name = match self.name {
+            Some(ref name) => name.clone(),
+            None => return Ok(()),
+        };
+        if self.shared.state.lock().snapshots.contains_key(&name) {
+            return Ok(());
+        }
+        let size = try!(self.inner.file_size()) as uint;
+        let mut contents = Vec::from_elem(size, 0u8);
+        let n = try!(self.inner.read(contents.as_mut_slice(), 0));
+        contents.truncate(n);
+        self.shared.state.lock().snapshots.insert(name, contents);
+        Ok(())
+    }
+
+    fn traced(&mut self, op: IoOp, offset: u64, len: uint, f: |&mut FaultFile| -> VfsResult<()>) -> VfsResult<()> {
+        let r = match self.shared.inject(op.clone()) {
+            Ok(()) => f(self),
+            Err(e) => Err(e),
+        };
+        self.shared.record(op, &self.name, offset, len, r.clone());
+        r
+    }
+}
+
+impl VfsFile for FaultFile {
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> VfsResult<uint> {
+        let len = buf.len();
+        let mut n = 0;
+        try!(self.traced(IoRead, offset, len, |file| {
+            n = try!(file.inner.read(buf, offset));
+            Ok(())
+        }));
+        Ok(n)
+    }
+
+    fn write(&mut self, buf: &[u8], offset: u64) -> VfsResult<()> {
+        self.traced(IoWrite, offset, buf.len(), |file| {
+            try!(file.snapshot());
+            file.inner.write(buf, offset)
+        })
+    }
+
+    fn truncate(&mut self, size: u64) -> VfsResult<()> {
+        self.traced(IoTruncate, size, 0, |file| {
+            try!(file.snapshot());
+            file.inner.truncate(size)
+        })
+    }
+
+    fn sync(&mut self, data_only: bool) -> VfsResult<()> {
+        self.traced(IoSync, 0, 0, |file| {
+            try!(file.inner.sync(data_only));
+            match file.name {
+                Some(ref name) => { file.shared.state.lock().snapshots.remove(name); }
+                None => {}
+            }
+            Ok(())
+        })
+    }
+
+    fn file_size(&mut self) -> VfsResult<u64> {
+        if self.shared.state.lock().powered_off {
+            return Err(SQLITE_IOERR);
+        }
+        self.inner.file_size()
+    }
+
+    fn lock(&mut self, level: LockLevel) -> VfsResult<()> {
+        self.inner.lock(level)
+    }
+
+    fn unlock(&mut self, level: LockLevel) -> VfsResult<()> {
+        self.inner.unlock(level)
+    }
+
+    fn check_reserved_lock(&mut self) -> VfsResult<bool> {
+        self.inner.check_reserved_lock()
+    }
+
+    fn sector_size(&self) -> uint {
+        self.inner.sector_size()
+    }
+
+    fn device_characteristics(&self) -> int {
+        self.inner.device_characteristics()
+    }
+
+    fn supports_shm(&self) -> bool {
+        self.inner.supports_shm()
+    }
+
+    fn shm_map(&mut self, region: uint, size: uint, extend: bool) -> VfsResult<*mut u8> {
+        self.inner.shm_map(region, size, extend)
+    }
+
+    fn shm_lock(&mut self, offset: uint, n: uint, lock: bool, exclusive: bool) -> VfsResult<()> {
+        self.inner.shm_lock(offset, n, lock, exclusive)
+    }
+
+    fn shm_barrier(&mut self) {
+        self.inner.shm_barrier()
+    }
+
+    fn shm_unmap(&mut self, delete: bool) -> VfsResult<()> {
+        self.inner.shm_unmap(delete)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::Database;
+    use memvfs::MemVfs;
+    use types::*;
+    use vfs::*;
+    use super::*;
+
+    fn setup(name: &str) -> FaultController {
+        let (vfs, faults) = FaultVfs::new(box MemVfs as Box<Vfs + Send + Sync>);
+        register_vfs(name, box vfs as Box<Vfs + Send + Sync>, false).unwrap();
+        faults
+    }
+
+    /// Each test registers its own VFS and keeps its database under the
+    /// same name in the shared memory VFS registry.
+    fn open(vfs: &str) -> Database {
+        let path = format!("{}.db", vfs);
+        Database::open_with_flags(path.as_slice(), SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE, Some(vfs)).unwrap()
+    }
+
+    fn query_int(database: &Database, sql: &str) -> int {
+        let cursor = database.prepare(sql).unwrap();
+        assert_eq!(cursor.step(), Ok(SQLITE_ROW));
+        cursor.get_int(0)
+    }
+
+    fn assert_intact(database: &Database) {
+        let cursor = database.prepare("PRAGMA integrity_check").unwrap();
+        assert_eq!(cursor.step(), Ok(SQLITE_ROW));
+        assert_eq!(cursor.get_text(0).as_slice(), "ok");
+    }
+
+    /// Runs a transaction that adds a row and a large blob, so that it
+    /// touches several pages.
+    fn add_row(database: &Database) -> bool {
+        let r = database.exec("BEGIN;
+                               INSERT INTO test VALUES (randomblob(5000));
+                               INSERT INTO test VALUES (randomblob(5000));
+                               COMMIT;");
+        if r.is_err() && !database.is_autocommit() {
+            let _ = database.exec("ROLLBACK");
+        }
+        r.is_ok()
+    }
+
+    fn check_errors_roll_back(vfs: &str, op: IoOp, error: ResultError) {
+        let faults = setup(vfs);
+        let database = open(vfs);
+        database.exec("CREATE TABLE test (v BLOB)").unwrap();
+
+        let mut n = 1;
+        loop {
+            let before = query_int(&database, "SELECT count(*) FROM test");
+            faults.fail_nth(op.clone(), n, FaultError(error));
+            let ok = add_row(&database);
+            let fired = faults.pending_faults() == 0;
+            faults.clear_faults();
+
+            let after = query_int(&database, "SELECT count(*) FROM test");
+            assert_eq!(after, if ok { before + 2 } else { before });
+            assert_intact(&database);
+            if !fired {
+                break;
+            }
+            n += 1;
+        }
+        assert!(n > 1);
+    }
+
+    #[test]
+    fn write_errors_roll_back() {
+        check_errors_roll_back("faultvfs-write", IoWrite, SQLITE_IOERR);
+    }
+
+    #[test]
+    fn sync_errors_roll_back() {
+        check_errors_roll_back("faultvfs-sync", IoSync, SQLITE_IOERR);
+    }
+
+    #[test]
+    fn disk_full_rolls_back() {
+        check_errors_roll_back("faultvfs-full", IoWrite, SQLITE_FULL);
+    }
+
+    #[test]
+    fn power_loss_keeps_committed_data() {
+        let vfs = "faultvfs-power";
+        let faults = setup(vfs);
+        open(vfs).exec("CREATE TABLE test (v BLOB)").unwrap();
+
+        let mut n = 1;
+        loop {
+            let before = query_int(&open(vfs), "SELECT count(*) FROM test");
+            faults.fail_nth(IoWrite, n, FaultPowerLoss);
+            let ok = add_row(&open(vfs));
+            let fired = faults.pending_faults() == 0;
+            faults.clear_faults();
+            faults.power_on();
+
+            let database = open(vfs);
+            let after = query_int(&database, "SELECT count(*) FROM test");
+            if ok {
+                assert_eq!(after, before + 2);
+            } else {
+                assert!(after == before || after == before + 2);
+            }
+            assert_intact(&database);
+            if !fired {
+                break;
+            }
+            n += 1;
+        }
+        assert!(n > 1);
+    }
+
+    #[test]
+    fn trace() {
+        let vfs = "faultvfs-trace";
+        let faults = setup(vfs);
+        let database = open(vfs);
+        database.exec("CREATE TABLE test (v BLOB)").unwrap();
+
+        faults.start_trace();
+        assert!(add_row(&database));
+        let trace = faults.take_trace();
+        assert!(faults.take_trace().is_empty());
+
+        let journal = Some("faultvfs-trace.db-journal".to_string());
+        let main = Some("faultvfs-trace.db".to_string());
+        assert!(trace.iter().any(|e| e.op == IoWrite && e.file == journal));
+        assert!(trace.iter().any(|e| e.op == IoWrite && e.file == main));
+        assert!(trace.iter().any(|e| e.op == IoSync && e.file == main));
+        assert!(trace.iter().any(|e| e.op == IoDelete && e.file == journal));
+        assert!(trace.iter().all(|e| e.result.is_ok()));
+        assert!(faults.count(IoWrite) >= trace.iter().filter(|e| e.op == IoWrite).count());
+    }
+}
@@ -35,16 +35,37 @@ use libc::*;
 
 pub enum dbh {}
 pub enum stmt {}
-pub enum _notused {}
+pub enum value {}
+pub enum backup {}
+pub enum context {}
+pub enum index_info {}
 
-#[link(name = "sqlite3")]
+// A loadable extension calls SQLite through the API routines table the
+// host passes it, so these forward to that table instead.
+#[cfg(feature = "loadable_extension")]
+pub use api_routines::*;
+
+#[cfg(not(feature = "loadable_extension"))]
+#[cfg_attr(not(feature = "bundled"), link(name = "sqlite3"))]
 extern {
     pub fn sqlite3_open(path: *const c_char, hnd: *mut *mut dbh) -> c_int;
+    pub fn sqlite3_open_v2(
+        path: *const c_char,
+        hnd: *mut *mut dbh,
+        flags: c_int,
+        vfs: *const c_char
+    ) -> c_int;
+    pub fn sqlite3_threadsafe() -> c_int;
+    pub fn sqlite3_libversion() -> *const c_char;
+    pub fn sqlite3_libversion_number() -> c_int;
+    pub fn sqlite3_sourceid() -> *const c_char;
+    pub fn sqlite3_compileoption_used(name: *const c_char) -> c_int;
     pub fn sqlite3_close_v2(dbh: *mut dbh) -> c_int;
     pub fn sqlite3_errmsg(dbh: *mut dbh) -> *const c_char;
     pub fn sqlite3_changes(dbh: *mut dbh) -> c_int;
     pub fn sqlite3_last_insert_rowid(dbh: *mut dbh) -> i64;
     pub fn sqlite3_complete(sql: *const c_char) -> c_int;
+    pub fn sqlite3_get_autocommit(dbh: *mut dbh) -> c_int;
 
     pub fn sqlite3_prepare_v2(
         hnd: *mut dbh,
@@ -57,10 +78,11 @@ extern {
     pub fn sqlite3_exec(
         dbh: *mut dbh,
         sql: *const c_char,
-        cb: *mut _notused,
-        d: *mut _notused,
+        cb: Option<extern "C" fn(*mut c_void, c_int, *mut *mut c_char, *mut *mut c_char) -> c_int>,
+        d: *mut c_void,
         err: *mut *mut c_char
     ) -> c_int;
+    pub fn sqlite3_free(p: *mut c_void);
 
     pub fn sqlite3_step(sth: *mut stmt) -> c_int;
     pub fn sqlite3_reset(sth: *mut stmt) -> c_int;
@@ -70,6 +92,8 @@ extern {
     pub fn sqlite3_column_name(sth: *mut stmt, icol: c_int) -> *const c_char;
     pub fn sqlite3_column_type(sth: *mut stmt, icol: c_int) -> c_int;
     pub fn sqlite3_data_count(sth: *mut stmt) -> c_int;
+    pub fn sqlite3_column_count(sth: *mut stmt) -> c_int;
+    pub fn sqlite3_column_decltype(sth: *mut stmt, icol: c_int) -> *const c_char;
     pub fn sqlite3_column_bytes(sth: *mut stmt, icol: c_int) -> c_int;
     pub fn sqlite3_column_blob(sth: *mut stmt, icol: c_int) -> *const u8;
 
@@ -86,5 +110,324 @@ extern {
     pub fn sqlite3_bind_double(sth: *mut stmt, icol: c_int, value: f64) -> c_int;
     pub fn sqlite3_bind_parameter_index(sth: *mut stmt, name: *const c_char) -> c_int;
 
+    pub fn sqlite3_update_hook(
+        dbh: *mut dbh,
+        cb: Option<extern "C" fn(*mut c_void, c_int, *const c_char, *const c_char, i64)>,
+        arg: *mut c_void
+    ) -> *mut c_void;
+    pub fn sqlite3_commit_hook(
+        dbh: *mut dbh,
+        cb: Option<extern "C" fn(*mut c_void) -> c_int>,
+        arg: *mut c_void
+    ) -> *mut c_void;
+    pub fn sqlite3_rollback_hook(
+        dbh: *mut dbh,
+        cb: Option<extern "C" fn(*mut c_void)>,
+        arg: *mut c_void
+    ) -> *mut c_void;
+    pub fn sqlite3_wal_hook(
+        dbh: *mut dbh,
+        cb: Option<extern "C" fn(*mut c_void, *mut dbh, *const c_char, c_int) -> c_int>,
+        arg: *mut c_void
+    ) -> *mut c_void;
+
+    pub fn sqlite3_value_type(v: *mut value) -> c_int;
+    pub fn sqlite3_value_bytes(v: *mut value) -> c_int;
+    pub fn sqlite3_value_blob(v: *mut value) -> *const u8;
+    pub fn sqlite3_value_text(v: *mut value) -> *const c_char;
+    pub fn sqlite3_value_double(v: *mut value) -> f64;
+    pub fn sqlite3_value_int64(v: *mut value) -> i64;
+
+    pub fn sqlite3_trace_v2(
+        dbh: *mut dbh,
+        mask: c_uint,
+        cb: Option<extern "C" fn(c_uint, *mut c_void, *mut c_void, *mut c_void) -> c_int>,
+        arg: *mut c_void
+    ) -> c_int;
+    pub fn sqlite3_sql(sth: *mut stmt) -> *const c_char;
+    pub fn sqlite3_expanded_sql(sth: *mut stmt) -> *mut c_char;
+
+    pub fn sqlite3_set_authorizer(
+        dbh: *mut dbh,
+        cb: Option<extern "C" fn(*mut c_void, c_int, *const c_char, *const c_char,
+                                 *const c_char, *const c_char) -> c_int>,
+        arg: *mut c_void
+    ) -> c_int;
+
+    pub fn sqlite3_wal_checkpoint_v2(
+        dbh: *mut dbh,
+        db_name: *const c_char,
+        mode: c_int,
+        log: *mut c_int,
+        ckpt: *mut c_int
+    ) -> c_int;
+    pub fn sqlite3_wal_autocheckpoint(dbh: *mut dbh, n: c_int) -> c_int;
+
+    pub fn sqlite3_backup_init(
+        dest: *mut dbh,
+        dest_name: *const c_char,
+        source: *mut dbh,
+        source_name: *const c_char
+    ) -> *mut backup;
+    pub fn sqlite3_backup_step(b: *mut backup, pages: c_int) -> c_int;
+    pub fn sqlite3_backup_finish(b: *mut backup) -> c_int;
+
+    pub fn sqlite3_db_config(dbh: *mut dbh, op: c_int, ...) -> c_int;
+    pub fn sqlite3_load_extension(
+        dbh: *mut dbh,
+        path: *const c_char,
+        entry_point: *const c_char,
+        err: *mut *mut c_char
+    ) -> c_int;
+
+    pub fn sqlite3_malloc64(n: u64) -> *mut c_void;
+    pub fn sqlite3_serialize(
+        dbh: *mut dbh,
+        schema: *const c_char,
+        size: *mut i64,
+        flags: c_uint
+    ) -> *mut u8;
+    pub fn sqlite3_deserialize(
+        dbh: *mut dbh,
+        schema: *const c_char,
+        data: *mut u8,
+        size: i64,
+        buf_size: i64,
+        flags: c_uint
+    ) -> c_int;
+    pub fn sqlite3_errcode(dbh: *mut dbh) -> c_int;
+
     pub fn sqlite3_busy_timeout(dbh: *mut dbh, ms: c_int) -> c_int;
+    pub fn sqlite3_busy_handler(
+        dbh: *mut dbh,
+        cb: Option<extern "C" fn(*mut c_void, c_int) -> c_int>,
+        arg: *mut c_void
+    ) -> c_int;
+
+    pub fn sqlite3_create_function_v2(
+        dbh: *mut dbh,
+        name: *const c_char,
+        n_arg: c_int,
+        flags: c_int,
+        app: *mut c_void,
+        func: Option<extern "C" fn(*mut context, c_int, *mut *mut value)>,
+        step: Option<extern "C" fn(*mut context, c_int, *mut *mut value)>,
+        fin: Option<extern "C" fn(*mut context)>,
+        destroy: Option<extern "C" fn(*mut c_void)>
+    ) -> c_int;
+    pub fn sqlite3_create_collation_v2(
+        dbh: *mut dbh,
+        name: *const c_char,
+        text_rep: c_int,
+        arg: *mut c_void,
+        compare: Option<extern "C" fn(*mut c_void, c_int, *const c_void, c_int, *const c_void) -> c_int>,
+        destroy: Option<extern "C" fn(*mut c_void)>
+    ) -> c_int;
+    pub fn sqlite3_create_module_v2(
+        dbh: *mut dbh,
+        name: *const c_char,
+        module: *const sqlite3_module,
+        aux: *mut c_void,
+        destroy: Option<extern "C" fn(*mut c_void)>
+    ) -> c_int;
+    pub fn sqlite3_declare_vtab(dbh: *mut dbh, sql: *const c_char) -> c_int;
+    pub fn sqlite3_user_data(ctx: *mut context) -> *mut c_void;
+    pub fn sqlite3_result_null(ctx: *mut context);
+    pub fn sqlite3_result_int64(ctx: *mut context, v: i64);
+    pub fn sqlite3_result_double(ctx: *mut context, v: f64);
+    pub fn sqlite3_result_text(ctx: *mut context, v: *const c_char, n: c_int, d: *mut c_void);
+    pub fn sqlite3_result_blob(ctx: *mut context, v: *const c_void, n: c_int, d: *mut c_void);
+    pub fn sqlite3_result_error(ctx: *mut context, msg: *const c_char, n: c_int);
+    pub fn sqlite3_result_error_code(ctx: *mut context, code: c_int);
+
+    pub fn sqlite3_interrupt(dbh: *mut dbh);
+    pub fn sqlite3_is_interrupted(dbh: *mut dbh) -> c_int;
+    pub fn sqlite3_progress_handler(
+        dbh: *mut dbh,
+        n_ops: c_int,
+        cb: Option<extern "C" fn(*mut c_void) -> c_int>,
+        arg: *mut c_void
+    );
+}
+
+#[cfg(feature = "preupdate_hook")]
+#[cfg(not(feature = "loadable_extension"))]
+#[cfg_attr(not(feature = "bundled"), link(name = "sqlite3"))]
+extern {
+    pub fn sqlite3_preupdate_hook(
+        dbh: *mut dbh,
+        cb: Option<extern "C" fn(*mut c_void, *mut dbh, c_int, *const c_char, *const c_char, i64, i64)>,
+        arg: *mut c_void
+    ) -> *mut c_void;
+    pub fn sqlite3_preupdate_old(dbh: *mut dbh, icol: c_int, v: *mut *mut value) -> c_int;
+    pub fn sqlite3_preupdate_new(dbh: *mut dbh, icol: c_int, v: *mut *mut value) -> c_int;
+    pub fn sqlite3_preupdate_count(dbh: *mut dbh) -> c_int;
+    pub fn sqlite3_preupdate_depth(dbh: *mut dbh) -> c_int;
+}
+
+#[cfg(feature = "column_metadata")]
+#[cfg(not(feature = "loadable_extension"))]
+#[cfg_attr(not(feature = "bundled"), link(name = "sqlite3"))]
+extern {
+    pub fn sqlite3_column_database_name(sth: *mut stmt, icol: c_int) -> *const c_char;
+    pub fn sqlite3_column_table_name(sth: *mut stmt, icol: c_int) -> *const c_char;
+    pub fn sqlite3_column_origin_name(sth: *mut stmt, icol: c_int) -> *const c_char;
+    pub fn sqlite3_table_column_metadata(
+        dbh: *mut dbh,
+        db_name: *const c_char,
+        table_name: *const c_char,
+        column_name: *const c_char,
+        data_type: *mut *const c_char,
+        coll_seq: *mut *const c_char,
+        not_null: *mut c_int,
+        primary_key: *mut c_int,
+        autoinc: *mut c_int
+    ) -> c_int;
+}
+
+#[repr(C)]
+pub struct sqlite3_file {
+    pub methods: *const sqlite3_io_methods,
+}
+
+#[repr(C)]
+pub struct sqlite3_io_methods {
+    pub version: c_int,
+    pub close: Option<extern "C" fn(*mut sqlite3_file) -> c_int>,
+    pub read: Option<extern "C" fn(*mut sqlite3_file, *mut c_void, c_int, i64) -> c_int>,
+    pub write: Option<extern "C" fn(*mut sqlite3_file, *const c_void, c_int, i64) -> c_int>,
+    pub truncate: Option<extern "C" fn(*mut sqlite3_file, i64) -> c_int>,
+    pub sync: Option<extern "C" fn(*mut sqlite3_file, c_int) -> c_int>,
+    pub file_size: Option<extern "C" fn(*mut sqlite3_file, *mut i64) -> c_int>,
+    pub lock: Option<extern "C" fn(*mut sqlite3_file, c_int) -> c_int>,
+    pub unlock: Option<extern "C" fn(*mut sqlite3_file, c_int) -> c_int>,
+    pub check_reserved_lock: Option<extern "C" fn(*mut sqlite3_file, *mut c_int) -> c_int>,
+    pub file_control: Option<extern "C" fn(*mut sqlite3_file, c_int, *mut c_void) -> c_int>,
+    pub sector_size: Option<extern "C" fn(*mut sqlite3_file) -> c_int>,
+    pub device_characteristics: Option<extern "C" fn(*mut sqlite3_file) -> c_int>,
+    pub shm_map: Option<extern "C" fn(*mut sqlite3_file, c_int, c_int, c_int, *mut *mut c_void) -> c_int>,
+    pub shm_lock: Option<extern "C" fn(*mut sqlite3_file, c_int, c_int, c_int) -> c_int>,
+    pub shm_barrier: Option<extern "C" fn(*mut sqlite3_file)>,
+    pub shm_unmap: Option<extern "C" fn(*mut sqlite3_file, c_int) -> c_int>,
+}
+
+#[repr(C)]
+pub struct sqlite3_vfs {
+    pub version: c_int,
+    pub os_file_size: c_int,
+    pub max_pathname: c_int,
+    pub next: *mut sqlite3_vfs,
+    pub name: *const c_char,
+    pub app_data: *mut c_void,
+    pub open: Option<extern "C" fn(*mut sqlite3_vfs, *const c_char, *mut sqlite3_file, c_int, *mut c_int) -> c_int>,
+    pub delete: Option<extern "C" fn(*mut sqlite3_vfs, *const c_char, c_int) -> c_int>,
+    pub access: Option<extern "C" fn(*mut sqlite3_vfs, *const c_char, c_int, *mut c_int) -> c_int>,
+    pub full_pathname: Option<extern "C" fn(*mut sqlite3_vfs, *const c_char, c_int, *mut c_char) -> c_int>,
+    pub dl_open: Option<extern "C" fn(*mut sqlite3_vfs, *const c_char) -> *mut c_void>,
+    pub dl_error: Option<extern "C" fn(*mut sqlite3_vfs, c_int, *mut c_char)>,
+    pub dl_sym: Option<extern "C" fn(*mut sqlite3_vfs, *mut c_void, *const c_char) -> *mut c_void>,
+    pub dl_close: Option<extern "C" fn(*mut sqlite3_vfs, *mut c_void)>,
+    pub randomness: Option<extern "C" fn(*mut sqlite3_vfs, c_int, *mut c_char) -> c_int>,
+    pub sleep: Option<extern "C" fn(*mut sqlite3_vfs, c_int) -> c_int>,
+    pub current_time: Option<extern "C" fn(*mut sqlite3_vfs, *mut f64) -> c_int>,
+    pub get_last_error: Option<extern "C" fn(*mut sqlite3_vfs, c_int, *mut c_char) -> c_int>,
+    pub current_time_int64: Option<extern "C" fn(*mut sqlite3_vfs, *mut i64) -> c_int>,
+}
+
+/// Version 1 of `sqlite3_module`, the methods of a virtual table module.
+#[repr(C)]
+pub struct sqlite3_module {
+    pub version: c_int,
+    pub create: Option<extern "C" fn(*mut dbh, *mut c_void, c_int, *const *const c_char, *mut *mut sqlite3_vtab, *mut *mut c_char) -> c_int>,
+    pub connect: Option<extern "C" fn(*mut dbh, *mut c_void, c_int, *const *const c_char, *mut *mut sqlite3_vtab, *mut *mut c_char) -> c_int>,
+    pub best_index: Option<extern "C" fn(*mut sqlite3_vtab, *mut index_info) -> c_int>,
+    pub disconnect: Option<extern "C" fn(*mut sqlite3_vtab) -> c_int>,
+    pub destroy: Option<extern "C" fn(*mut sqlite3_vtab) -> c_int>,
+    pub open: Option<extern "C" fn(*mut sqlite3_vtab, *mut *mut sqlite3_vtab_cursor) -> c_int>,
+    pub close: Option<extern "C" fn(*mut sqlite3_vtab_cursor) -> c_int>,
+    pub filter: Option<extern "C" fn(*mut sqlite3_vtab_cursor, c_int, *const c_char, c_int, *mut *mut value) -> c_int>,
+    pub next: Option<extern "C" fn(*mut sqlite3_vtab_cursor) -> c_int>,
+    pub eof: Option<extern "C" fn(*mut sqlite3_vtab_cursor) -> c_int>,
+    pub column: Option<extern "C" fn(*mut sqlite3_vtab_cursor, *mut context, c_int) -> c_int>,
+    pub rowid: Option<extern "C" fn(*mut sqlite3_vtab_cursor, *mut i64) -> c_int>,
+    pub update: Option<extern "C" fn(*mut sqlite3_vtab, c_int, *mut *mut value, *mut i64) -> c_int>,
+    pub begin: Option<extern "C" fn(*mut sqlite3_vtab) -> c_int>,
+    pub sync: Option<extern "C" fn(*mut sqlite3_vtab) -> c_int>,
+    pub commit: Option<extern "C" fn(*mut sqlite3_vtab) -> c_int>,
+    pub rollback: Option<extern "C" fn(*mut sqlite3_vtab) -> c_int>,
+    pub find_function: Option<extern "C" fn(*mut sqlite3_vtab, c_int, *const c_char, *mut *mut c_void, *mut *mut c_void) -> c_int>,
+    pub rename: Option<extern "C" fn(*mut sqlite3_vtab, *const c_char) -> c_int>,
+}
+
+#[repr(C)]
+pub struct sqlite3_vtab {
+    pub module: *const sqlite3_module,
+    pub n_ref: c_int,
+    pub err_msg: *mut c_char,
+}
+
+#[repr(C)]
+pub struct sqlite3_vtab_cursor {
+    pub vtab: *mut sqlite3_vtab,
+}
+
+#[cfg(not(feature = "loadable_extension"))]
+#[cfg_attr(not(feature = "bundled"), link(name = "sqlite3"))]
+extern {
+    pub fn sqlite3_vfs_find(name: *const c_char) -> *mut sqlite3_vfs;
+    pub fn sqlite3_vfs_register(vfs: *mut sqlite3_vfs, make_default: c_int) -> c_int;
+}
+
+#[cfg(feature = "session")]
+#[cfg(not(feature = "loadable_extension"))]
+pub enum session {}
+#[cfg(feature = "session")]
+#[cfg(not(feature = "loadable_extension"))]
+pub enum changeset_iter {}
+
+#[cfg(feature = "session")]
+#[cfg(not(feature = "loadable_extension"))]
+#[cfg_attr(not(feature = "bundled"), link(name = "sqlite3"))]
+extern {
+    pub fn sqlite3session_create(dbh: *mut dbh, schema: *const c_char, out: *mut *mut session) -> c_int;
+    pub fn sqlite3session_delete(s: *mut session);
+    pub fn sqlite3session_attach(s: *mut session, table: *const c_char) -> c_int;
+    pub fn sqlite3session_enable(s: *mut session, enable: c_int) -> c_int;
+    pub fn sqlite3session_isempty(s: *mut session) -> c_int;
+    pub fn sqlite3session_changeset(s: *mut session, n: *mut c_int, out: *mut *mut c_void) -> c_int;
+    pub fn sqlite3session_patchset(s: *mut session, n: *mut c_int, out: *mut *mut c_void) -> c_int;
+
+    pub fn sqlite3changeset_apply(
+        dbh: *mut dbh,
+        n: c_int,
+        changeset: *mut c_void,
+        filter: Option<extern "C" fn(*mut c_void, *const c_char) -> c_int>,
+        conflict: Option<extern "C" fn(*mut c_void, c_int, *mut changeset_iter) -> c_int>,
+        arg: *mut c_void
+    ) -> c_int;
+    pub fn sqlite3changeset_invert(n: c_int, changeset: *const c_void, n_out: *mut c_int, out: *mut *mut c_void) -> c_int;
+    pub fn sqlite3changeset_concat(
+        n_a: c_int,
+        a: *mut c_void,
+        n_b: c_int,
+        b: *mut c_void,
+        n_out: *mut c_int,
+        out: *mut *mut c_void
+    ) -> c_int;
+
+    pub fn sqlite3changeset_start(iter: *mut *mut changeset_iter, n: c_int, changeset: *mut c_void) -> c_int;
+    pub fn sqlite3changeset_next(iter: *mut changeset_iter) -> c_int;
+    pub fn sqlite3changeset_op(
+        iter: *mut changeset_iter,
+        table: *mut *const c_char,
+        n_col: *mut c_int,
+        op: *mut c_int,
+        indirect: *mut c_int
+    ) -> c_int;
+    pub fn sqlite3changeset_pk(iter: *mut changeset_iter, pk: *mut *mut u8, n_col: *mut c_int) -> c_int;
+    pub fn sqlite3changeset_old(iter: *mut changeset_iter, i: c_int, out: *mut *mut value) -> c_int;
+    pub fn sqlite3changeset_new(iter: *mut changeset_iter, i: c_int, out: *mut *mut value) -> c_int;
+    pub fn sqlite3changeset_conflict(iter: *mut changeset_iter, i: c_int, out: *mut *mut value) -> c_int;
+    pub fn sqlite3changeset_fk_conflicts(iter: *mut changeset_iter, n: *mut c_int) -> c_int;
+    pub fn sqlite3changeset_finalize(iter: *mut changeset_iter) -> c_int;
 }
@@ -0,0 +1,393 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! An in-memory VFS whose databases can be shared between connections.
+//!
+//! Files live in a process-wide registry under the name they were opened
+//! with, so every connection opened on the same name in this VFS sees the
+//! same database until `delete` is called. Databases can also be copied,
+//! dumped to bytes and loaded back without going through SQL.
+//!
+//! Shared memory is not supported, so WAL mode is only available with
+//! `PRAGMA locking_mode=EXCLUSIVE`.
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex, Once, ONCE_INIT};
+
+use database::Database;
+use types::*;
+use vfs::*;
+
+/// The name the memory VFS is registered under.
+pub static MEMVFS: &'static str = "memvfs";
+
+struct MemData {
+    bytes: Vec<u8>,
+    // open `MemFile`s, which may cache pages of `bytes`
+    handles: uint,
+    shared: uint,
+    reserved: bool,
+    pending: bool,
+    exclusive: bool,
+}
+
+type Registry = Mutex<HashMap<String, Arc<Mutex<MemData>>>>;
+
+static mut REGISTRY: *const Registry = 0 as *const Registry;
+static REGISTRY_INIT: Once = ONCE_INIT;
+static REGISTER: Once = ONCE_INIT;
+static mut REGISTER_RESULT: i32 = 0;
+
+fn registry() -> &'static Registry {
+    unsafe {
+        REGISTRY_INIT.doit(|| {
+            let registry: Box<Registry> = box Mutex::new(HashMap::new());
+            REGISTRY = mem::transmute(registry);
+        });
+        &*REGISTRY
+    }
+}
+
+fn new_data(bytes: Vec<u8>) -> Arc<Mutex<MemData>> {
+    Arc::new(Mutex::new(MemData {
+        bytes: bytes,
+        handles: 0,
+        shared: 0,
+        reserved: false,
+        pending: false,
+        exclusive: false,
+    }))
+}
+
+/// Registers the memory VFS under `MEMVFS`. Calling this more than once
+/// is harmless.
+pub fn register() -> SqliteResult<()> {
+    unsafe {
+        REGISTER.doit(|| {
+            REGISTER_RESULT = match register_vfs(MEMVFS, box MemVfs as Box<Vfs + Send + Sync>, false) {
+                Ok(()) => SQLITE_OK as i32,
+                Err(e) => e as i32,
+            };
+        });
+        check(REGISTER_RESULT)
+    }
+}
+
+/// Opens the in-memory database `name`, creating it if needed.
+pub fn open(name: &str) -> SqliteResult<Database> {
+    try!(register());
+    Database::open_with_flags(name, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE, Some(MEMVFS))
+}
+
+/// Returns whether the in-memory database `name` exists.
+pub fn exists(name: &str) -> bool {
+    registry().lock().contains_key(&name.to_string())
+}
+
+/// Returns a copy of the committed contents of `name`. Fails with
+/// `SQLITE_BUSY` while a connection is writing to it.
+pub fn dump(name: &str) -> SqliteResult<Vec<u8>> {
+    let data = match registry().lock().find(&name.to_string()) {
+        Some(data) => data.clone(),
+        None => return Err(SQLITE_CANTOPEN),
+    };
+    let data = data.lock();
+    if data.exclusive || data.pending {
+        return Err(SQLITE_BUSY);
+    }
+    Ok(data.bytes.clone())
+}
+
+/// Creates or replaces the in-memory database `name` with `bytes`, which
+/// must be a database image such as one returned by `dump`. Fails with
+/// `SQLITE_BUSY` while any connection has `name` open, even if idle,
+/// since it may have pages of the old contents cached.
+pub fn load(name: &str, bytes: &[u8]) -> SqliteResult<()> {
+    let mut registry = registry().lock();
+    let existing = registry.find(&name.to_string()).map(|data| data.clone());
+    match existing {
+        Some(data) => {
+            let mut data = data.lock();
+            if data.handles > 0 {
+                return Err(SQLITE_BUSY);
+            }
+            data.bytes = Vec::from_slice(bytes);
+        }
+        None => {
+            registry.insert(name.to_string(), new_data(Vec::from_slice(bytes)));
+        }
+    }
+    Ok(())
+}
+
+/// Copies the committed contents of `from` to a new or replaced database
+/// `to`. Fails with `SQLITE_BUSY` if `to` is open, like `load`.
+pub fn clone_database(from: &str, to: &str) -> SqliteResult<()> {
+    let bytes = try!(dump(from));
+    load(to, bytes.as_slice())
+}
+
+/// Removes `name` and its journal from the registry. Connections that
+/// still have it open keep working on their copy.
+pub fn delete(name: &str) -> SqliteResult<()> {
+    let mut registry = registry().lock();
+    registry.remove(&format!("{}-journal", name));
+    if registry.remove(&name.to_string()) { Ok(()) } else { Err(SQLITE_CANTOPEN) }
+}
+
+/// The memory VFS itself, for wrapping in another VFS such as
+/// `faultvfs::FaultVfs`. Files it opens share the registry with `MEMVFS`.
+pub struct MemVfs;
+
+struct MemFile {
+    name: Option<String>,
+    data: Arc<Mutex<MemData>>,
+    level: LockLevel,
+    delete_on_close: bool,
+}
+
+impl Vfs for MemVfs {
+    fn open(&self, name: Option<&str>, flags: OpenFlags) -> VfsResult<Box<VfsFile + Send>> {
+        let data = match name {
+            None => new_data(Vec::new()),
+            Some(name) => {
+                let mut registry = registry().lock();
+                let key = name.to_string();
+                let existing = registry.find(&key).map(|data| data.clone());
+                match existing {
+                    Some(data) => data,
+                    None if flags.contains(SQLITE_OPEN_CREATE) => {
+                        let data = new_data(Vec::new());
+                        registry.insert(key, data.clone());
+                        data
+                    }
+                    None => return Err(SQLITE_CANTOPEN),
+                }
+            }
+        };
+        data.lock().handles += 1;
+        Ok(box MemFile {
+            name: name.map(|n| n.to_string()),
+            data: data,
+            level: LockNone,
+            delete_on_close: flags.contains(SQLITE_OPEN_DELETEONCLOSE),
+        } as Box<VfsFile + Send>)
+    }
+
+    fn delete(&self, name: &str, _sync_dir: bool) -> VfsResult<()> {
+        registry().lock().remove(&name.to_string());
+        Ok(())
+    }
+
+    fn access(&self, name: &str, _check: AccessCheck) -> VfsResult<bool> {
+        Ok(exists(name))
+    }
+}
+
+impl Drop for MemFile {
+    fn drop(&mut self) {
+        let _ = self.unlock(LockNone);
+        self.data.lock().handles -= 1;
+        if self.delete_on_close {
+            match self.name {
+                Some(ref name) => { registry().lock().remove(name); }
+                None => {}
+            }
+        }
+    }
+}
+
+impl VfsFile for MemFile {
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> VfsResult<uint> {
+        let data = self.data.lock();
+        let offset = offset as uint;
+        if offset >= data.bytes.len() {
+            return Ok(0);
+        }
+        let src = data.bytes.slice_from(offset);
+        let n = if src.len() < buf.len() { src.len() } else { buf.len() };
+        buf.mut_slice_to(n).copy_from(src.slice_to(n));
+        Ok(n)
+    }
+
+    fn write(&mut self, buf: &[u8], offset: u64) -> VfsResult<()> {
+        let mut data = self.data.lock();
+        let offset = offset as uint;
+        let end = offset + buf.len();
+        if data.bytes.len() < end {
+            data.bytes.grow(end - data.bytes.len(), &0u8);
+        }
+        data.bytes.mut_slice(offset, end).copy_from(buf);
+        Ok(())
+    }
+
+    fn truncate(&mut self, size: u64) -> VfsResult<()> {
+        self.data.lock().bytes.truncate(size as uint);
+        Ok(())
+    }
+
+    fn sync(&mut self, _data_only: bool) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn file_size(&mut self) -> VfsResult<u64> {
+        Ok(self.data.lock().bytes.len() as u64)
+    }
+
+    fn lock(&mut self, level: LockLevel) -> VfsResult<()> {
+        if level <= self.level {
+            return Ok(());
+        }
+        let mut data = self.data.lock();
+        match level {
+            LockShared => {
+                if data.pending || data.exclusive {
+                    return Err(SQLITE_BUSY);
+                }
+                data.shared += 1;
+            }
+            LockReserved => {
+                if data.reserved {
+                    return Err(SQLITE_BUSY);
+                }
+                data.reserved = true;
+            }
+            LockPending | LockExclusive => {
+                // Holding PENDING keeps new readers out while we wait for
+                // the existing ones to finish.
+                if self.level < LockPending {
+                    data.pending = true;
+                    self.level = LockPending;
+                }
+                if level == LockExclusive {
+                    if data.shared > 1 {
+                        return Err(SQLITE_BUSY);
+                    }
+                    data.exclusive = true;
+                }
+            }
+            LockNone => {}
+        }
+        self.level = level;
+        Ok(())
+    }
+
+    fn unlock(&mut self, level: LockLevel) -> VfsResult<()> {
+        if level >= self.level {
+            return Ok(());
+        }
+        let mut data = self.data.lock();
+        if self.level > LockShared && level <= LockShared {
+            if self.level >= LockReserved {
+                data.reserved = false;
+            }
+            if self.level >= LockPending {
+                data.pending = false;
+            }
+            if self.level == LockExclusive {
+                data.exclusive = false;
+            }
+        }
+        if level == LockNone && self.level >= LockShared {
+            data.shared -= 1;
+        }
+        self.level = level;
+        Ok(())
+    }
+
+    fn check_reserved_lock(&mut self) -> VfsResult<bool> {
+        let data = self.data.lock();
+        Ok(data.reserved || data.pending || data.exclusive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use types::*;
+    use super::*;
+
+    fn count(database: &::database::Database) -> int {
+        let cursor = database.prepare("SELECT count(*) FROM test").unwrap();
+        assert_eq!(cursor.step(), Ok(SQLITE_ROW));
+        cursor.get_int(0)
+    }
+
+    #[test]
+    fn shared_between_connections() {
+        let a = open("memvfs-shared").unwrap();
+        let b = open("memvfs-shared").unwrap();
+        a.exec("CREATE TABLE test (v INTEGER); INSERT INTO test VALUES (1);").unwrap();
+        assert_eq!(count(&b), 1);
+
+        a.exec("BEGIN IMMEDIATE").unwrap();
+        assert_eq!(b.exec("BEGIN IMMEDIATE"), Err(SQLITE_BUSY));
+        a.exec("ROLLBACK").unwrap();
+
+        let other = open("memvfs-other").unwrap();
+        assert!(other.exec("SELECT * FROM test").is_err());
+
+        drop(a);
+        drop(b);
+        drop(other);
+        assert_eq!(delete("memvfs-shared"), Ok(()));
+        assert_eq!(delete("memvfs-other"), Ok(()));
+        assert!(!exists("memvfs-shared"));
+    }
+
+    #[test]
+    fn clone_and_dump() {
+        let database = open("memvfs-original").unwrap();
+        database.exec("CREATE TABLE test (v INTEGER); INSERT INTO test VALUES (1);").unwrap();
+
+        assert_eq!(clone_database("memvfs-original", "memvfs-clone"), Ok(()));
+        database.exec("INSERT INTO test VALUES (2)").unwrap();
+        assert_eq!(count(&database), 2);
+        assert_eq!(count(&open("memvfs-clone").unwrap()), 1);
+
+        let bytes = dump("memvfs-original").unwrap();
+        assert_eq!(bytes.slice_to(16), b"SQLite format 3\0".as_slice());
+        assert_eq!(load("memvfs-loaded", bytes.as_slice()), Ok(()));
+        assert_eq!(count(&open("memvfs-loaded").unwrap()), 2);
+
+        database.exec("BEGIN; INSERT INTO test VALUES (3);").unwrap();
+        assert_eq!(load("memvfs-original", bytes.as_slice()), Err(SQLITE_BUSY));
+        database.exec("ROLLBACK").unwrap();
+        assert_eq!(load("memvfs-original", bytes.as_slice()), Err(SQLITE_BUSY));
+        drop(database);
+        assert_eq!(load("memvfs-original", bytes.as_slice()), Ok(()));
+
+        assert_eq!(dump("memvfs-missing"), Err(SQLITE_CANTOPEN));
+        for name in ["memvfs-original", "memvfs-clone", "memvfs-loaded"].iter() {
+            assert_eq!(delete(*name), Ok(()));
+        }
+    }
+}
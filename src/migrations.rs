@@ -0,0 +1,404 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Applying numbered schema migrations.
+//!
+//! A `Migrations` list is applied in order inside a single transaction.
+//! Progress is tracked either in `PRAGMA user_version` or in a history
+//! table that also records a checksum of each migration, so that editing
+//! a migration after it has been applied is detected.
+//!
+//! SQL migrations must not contain their own `BEGIN`/`COMMIT`.
+
+use pragma::quote_identifier;
+use database::Database;
+use types::*;
+
+/// Rust code run by a migration, see `Migration::code`.
+pub type MigrationFn = Box<for<'a> Fn<(&'a Database,), Result<(), SqliteError>> + Send>;
+
+/// A migration body.
+pub enum Action {
+    /// SQL run with `Database::exec`.
+    SqlAction(String),
+    /// Rust code run against the connection.
+    CodeAction(MigrationFn),
+}
+
+/// One numbered migration, with an optional way to undo it.
+pub struct Migration {
+    pub version: i64,
+    pub name: String,
+    pub up: Action,
+    pub down: Option<Action>,
+}
+
+impl Migration {
+    /// A migration that runs `up`.
+    pub fn sql(version: i64, name: &str, up: &str) -> Migration {
+        Migration { version: version, name: name.to_string(), up: SqlAction(up.to_string()), down: None }
+    }
+
+    /// A migration that calls `up`, which may capture its environment.
+    pub fn code<F>(version: i64, name: &str, up: F) -> Migration
+            where F: for<'a> Fn<(&'a Database,), Result<(), SqliteError>> + Send {
+        Migration { version: version, name: name.to_string(), up: CodeAction(box up as MigrationFn), down: None }
+    }
+
+    /// Sets the SQL that undoes this migration.
+    pub fn down_sql(self, down: &str) -> Migration {
+        Migration { down: Some(SqlAction(down.to_string())), ..self }
+    }
+
+    /// Sets the closure that undoes this migration.
+    pub fn down_code<F>(self, down: F) -> Migration
+            where F: for<'a> Fn<(&'a Database,), Result<(), SqliteError>> + Send {
+        Migration { down: Some(CodeAction(box down as MigrationFn)), ..self }
+    }
+
+    /// A 64-bit FNV-1a hash of the name and, for SQL migrations, the SQL
+    /// text. Code migrations can only be checked by name.
+    pub fn checksum(&self) -> String {
+        let mut h = 0xcbf29ce484222325u64;
+        {
+            let feed = |bytes: &[u8]| {
+                for b in bytes.iter() {
+                    h = (h ^ *b as u64) * 0x100000001b3u64;
+                }
+            };
+            feed(self.name.as_bytes());
+            match self.up {
+                SqlAction(ref sql) => { feed(&[0]); feed(sql.as_bytes()) }
+                CodeAction(_) => {}
+            }
+        }
+        format!("{:016x}", h)
+    }
+}
+
+/// Where applied migrations are recorded.
+pub enum Tracking {
+    /// `PRAGMA user_version` holds the last applied version. Edited
+    /// migrations cannot be detected.
+    TrackUserVersion,
+    /// A table with one row per applied migration and its checksum.
+    TrackHistory(String),
+}
+
+/// Why migrating failed. Nothing is changed when an error is returned.
+#[deriving(PartialEq, Eq, Show)]
+pub enum MigrationError {
+    MigrationSqlite(SqliteError),
+    /// The versions are not strictly increasing or not positive.
+    BadOrder(i64),
+    /// The database has a version applied that is not in the list.
+    UnknownVersion(i64),
+    /// The migration was edited after it was applied.
+    ChecksumMismatch(i64),
+    /// A down migration was requested but not provided.
+    NoDownMigration(i64),
+}
+
+pub type MigrationResult<T> = Result<T, MigrationError>;
+
+fn sqlite(database: &Database, kind: ResultError) -> MigrationError {
+    MigrationSqlite(SqliteError { kind: kind, desc: database.get_errmsg() })
+}
+
+fn run(database: &Database, action: &Action) -> MigrationResult<()> {
+    match *action {
+        SqlAction(ref sql) => database.exec_with_message(sql.as_slice()),
+        CodeAction(ref f) => f.call((database,)),
+    }.map_err(MigrationSqlite)
+}
+
+/// An ordered list of migrations.
+pub struct Migrations {
+    migrations: Vec<Migration>,
+    tracking: Tracking,
+}
+
+impl Migrations {
+    /// Tracks progress in `PRAGMA user_version`.
+    pub fn new(migrations: Vec<Migration>) -> Migrations {
+        Migrations { migrations: migrations, tracking: TrackUserVersion }
+    }
+
+    /// Tracks progress in `table` instead, created by the first migration
+    /// that is applied.
+    pub fn with_history_table(self, table: &str) -> Migrations {
+        Migrations { tracking: TrackHistory(table.to_string()), ..self }
+    }
+
+    /// Returns the versions already applied, oldest first, after checking
+    /// them against the list. Nothing is written to the database.
+    pub fn applied(&self, database: &Database) -> MigrationResult<Vec<i64>> {
+        let mut last = 0;
+        for m in self.migrations.iter() {
+            if m.version <= last {
+                return Err(BadOrder(m.version));
+            }
+            last = m.version;
+        }
+
+        match self.tracking {
+            TrackUserVersion => {
+                let current = try!(database.user_version(None).map_err(|e| sqlite(database, e)));
+                if current != 0 && !self.migrations.iter().any(|m| m.version == current) {
+                    return Err(UnknownVersion(current));
+                }
+                Ok(self.migrations.iter().map(|m| m.version).filter(|v| *v <= current).collect())
+            }
+            TrackHistory(ref table) => {
+                if !try!(self.has_history(database, table.as_slice())) {
+                    return Ok(Vec::new());
+                }
+                let sql = format!("SELECT version, checksum FROM {} ORDER BY version",
+                                  quote_identifier(table.as_slice()));
+                let cursor = try!(database.prepare(sql.as_slice()).map_err(|e| sqlite(database, e)));
+                let mut applied = Vec::new();
+                loop {
+                    match try!(cursor.step().map_err(|e| sqlite(database, e))) {
+                        SQLITE_ROW => {
+                            let version = cursor.get_i64(0);
+                            match self.migrations.iter().find(|m| m.version == version) {
+                                None => return Err(UnknownVersion(version)),
+                                Some(m) if m.checksum() != cursor.get_text(1) => {
+                                    return Err(ChecksumMismatch(version))
+                                }
+                                Some(_) => applied.push(version)
+                            }
+                        }
+                        SQLITE_DONE => return Ok(applied)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the migrations not applied yet.
+    pub fn pending(&self, database: &Database) -> MigrationResult<Vec<&Migration>> {
+        let applied = try!(self.applied(database));
+        Ok(self.migrations.iter().filter(|m| !applied.contains(&m.version)).collect())
+    }
+
+    /// Applies every pending migration and returns their versions.
+    pub fn migrate(&self, database: &Database) -> MigrationResult<Vec<i64>> {
+        let target = self.migrations.last().map_or(0, |m| m.version);
+        self.migrate_to(database, target)
+    }
+
+    /// Applies pending migrations up to and including `target`, or undoes
+    /// applied migrations newer than `target`. Returns the versions
+    /// applied or undone, in the order that happened.
+    pub fn migrate_to(&self, database: &Database, target: i64) -> MigrationResult<Vec<i64>> {
+        self.transaction(database, target, false)
+    }
+
+    /// Like `migrate`, but rolls everything back at the end. Useful to
+    /// check that pending migrations succeed before deploying them.
+    pub fn dry_run(&self, database: &Database) -> MigrationResult<Vec<i64>> {
+        let target = self.migrations.last().map_or(0, |m| m.version);
+        self.transaction(database, target, true)
+    }
+
+    fn transaction(&self, database: &Database, target: i64, dry_run: bool) -> MigrationResult<Vec<i64>> {
+        try!(database.exec_with_message("BEGIN IMMEDIATE").map_err(MigrationSqlite));
+        let r = self.apply(database, target);
+        let end = if r.is_ok() && !dry_run { "COMMIT" } else { "ROLLBACK" };
+        match (r, database.exec_with_message(end)) {
+            (Ok(done), Ok(())) => Ok(done),
+            (Ok(_), Err(e)) => {
+                let _ = database.exec("ROLLBACK");
+                Err(MigrationSqlite(e))
+            }
+            (Err(e), _) => Err(e),
+        }
+    }
+
+    fn apply(&self, database: &Database, target: i64) -> MigrationResult<Vec<i64>> {
+        match self.tracking {
+            TrackHistory(ref table) => try!(self.create_history(database, table.as_slice())),
+            TrackUserVersion => {}
+        }
+        let applied = try!(self.applied(database));
+        let mut done = Vec::new();
+
+        // undo newer migrations, newest first
+        for m in self.migrations.iter().rev().filter(|m| m.version > target && applied.contains(&m.version)) {
+            debug!("`Migrations`: down {} {}", m.version, m.name);
+            match m.down {
+                Some(ref down) => try!(run(database, down)),
+                None => return Err(NoDownMigration(m.version))
+            }
+            let prev = self.migrations.iter().map(|p| p.version).filter(|v| *v < m.version).last();
+            try!(self.record(database, m, false, prev.unwrap_or(0)));
+            done.push(m.version);
+        }
+
+        for m in self.migrations.iter().filter(|m| m.version <= target && !applied.contains(&m.version)) {
+            debug!("`Migrations`: up {} {}", m.version, m.name);
+            try!(run(database, &m.up));
+            try!(self.record(database, m, true, m.version));
+            done.push(m.version);
+        }
+        Ok(done)
+    }
+
+    fn record(&self, database: &Database, m: &Migration, up: bool, version: i64) -> MigrationResult<()> {
+        match self.tracking {
+            TrackUserVersion => database.set_user_version(None, version).map_err(|e| sqlite(database, e)),
+            TrackHistory(ref table) => {
+                let table = quote_identifier(table.as_slice());
+                let cursor = if up {
+                    let sql = format!("INSERT INTO {} (version, name, checksum) VALUES (?, ?, ?)", table);
+                    let cursor = try!(database.prepare(sql.as_slice()).map_err(|e| sqlite(database, e)));
+                    try!(cursor.bind_params(&[Integer64(m.version), Text(m.name.clone()), Text(m.checksum())])
+                         .map_err(|e| sqlite(database, e)));
+                    cursor
+                } else {
+                    let sql = format!("DELETE FROM {} WHERE version = ?", table);
+                    let cursor = try!(database.prepare(sql.as_slice()).map_err(|e| sqlite(database, e)));
+                    try!(cursor.bind_param(1, &Integer64(m.version)).map_err(|e| sqlite(database, e)));
+                    cursor
+                };
+                cursor.step().map(|_| ()).map_err(|e| sqlite(database, e))
+            }
+        }
+    }
+
+    fn has_history(&self, database: &Database, table: &str) -> MigrationResult<bool> {
+        let cursor = try!(database.prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?")
+                          .map_err(|e| sqlite(database, e)));
+        try!(cursor.bind_param(1, &Text(table.to_string())).map_err(|e| sqlite(database, e)));
+        cursor.step().map(|r| r == SQLITE_ROW).map_err(|e| sqlite(database, e))
+    }
+
+    fn create_history(&self, database: &Database, table: &str) -> MigrationResult<()> {
+        database.exec_with_message(format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                checksum TEXT NOT NULL,
+                applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )", quote_identifier(table)).as_slice()).map_err(MigrationSqlite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::Database;
+    use types::*;
+    use super::*;
+
+    fn migrations() -> Vec<Migration> {
+        let (seed, unseed) = ("root".to_string(), "root".to_string());
+        vec!(Migration::sql(1, "person", "CREATE TABLE person (id INTEGER PRIMARY KEY, name TEXT)")
+                 .down_sql("DROP TABLE person"),
+             Migration::code(2, "seed", move |&: database: &Database| {
+                 database.exec_with_message(format!("INSERT INTO person (name) VALUES ('{}')", seed).as_slice())
+             }).down_code(move |&: database: &Database| {
+                 database.exec_with_message(format!("DELETE FROM person WHERE name = '{}'", unseed).as_slice())
+             }),
+             Migration::sql(3, "email", "ALTER TABLE person ADD COLUMN email TEXT")
+                 .down_sql("ALTER TABLE person DROP COLUMN email"))
+    }
+
+    fn count(database: &Database) -> int {
+        let cursor = database.prepare("SELECT count(*) FROM person").unwrap();
+        cursor.step().unwrap();
+        cursor.get_int(0)
+    }
+
+    #[test]
+    fn user_version_tracking() {
+        let database = Database::new(":memory:").unwrap();
+        let m = Migrations::new(migrations());
+        assert_eq!(m.migrate_to(&database, 2), Ok(vec!(1, 2)));
+        assert_eq!(database.user_version(None), Ok(2));
+        assert_eq!(m.migrate(&database), Ok(vec!(3)));
+        assert_eq!(m.migrate(&database), Ok(vec!()));
+        assert_eq!(count(&database), 1);
+
+        assert_eq!(m.migrate_to(&database, 1), Ok(vec!(3, 2)));
+        assert_eq!(database.user_version(None), Ok(1));
+        assert_eq!(count(&database), 0);
+    }
+
+    #[test]
+    fn history_tracking_detects_edits() {
+        let database = Database::new(":memory:").unwrap();
+        let m = Migrations::new(migrations()).with_history_table("history");
+        assert_eq!(m.pending(&database).unwrap().len(), 3);
+        assert_eq!(m.dry_run(&database), Ok(vec!(1, 2, 3)));
+        // Neither created the history table.
+        assert!(database.prepare("SELECT * FROM history").is_err());
+        assert_eq!(m.migrate(&database), Ok(vec!(1, 2, 3)));
+
+        let mut edited = migrations();
+        edited[0] = Migration::sql(1, "person", "CREATE TABLE person (id INTEGER PRIMARY KEY)");
+        let m = Migrations::new(edited).with_history_table("history");
+        assert_eq!(m.migrate(&database), Err(ChecksumMismatch(1)));
+
+        let mut fewer = migrations();
+        fewer.pop();
+        let m = Migrations::new(fewer).with_history_table("history");
+        assert_eq!(m.migrate(&database), Err(UnknownVersion(3)));
+    }
+
+    #[test]
+    fn failure_is_atomic() {
+        let database = Database::new(":memory:").unwrap();
+        let mut broken = migrations();
+        broken.push(Migration::sql(4, "broken", "CREATE TABLE person (x)"));
+        let m = Migrations::new(broken);
+        match m.migrate(&database) {
+            Err(MigrationSqlite(e)) => assert_eq!(e.kind, SQLITE_ERROR),
+            r => fail!("unexpected {}", r)
+        }
+        assert_eq!(database.user_version(None), Ok(0));
+        assert!(database.prepare("SELECT * FROM person").is_err());
+    }
+
+    #[test]
+    fn dry_run_and_missing_down() {
+        let database = Database::new(":memory:").unwrap();
+        let m = Migrations::new(vec!(Migration::sql(1, "t", "CREATE TABLE t (x)")));
+        assert_eq!(m.dry_run(&database), Ok(vec!(1)));
+        assert_eq!(m.pending(&database).unwrap().len(), 1);
+        assert_eq!(m.migrate(&database), Ok(vec!(1)));
+        assert_eq!(m.migrate_to(&database, 0), Err(NoDownMigration(1)));
+        assert_eq!(database.user_version(None), Ok(1));
+
+        let unordered = Migrations::new(vec!(Migration::sql(2, "a", ""), Migration::sql(1, "b", "")));
+        assert_eq!(unordered.migrate(&database), Err(BadOrder(1)));
+    }
+}
@@ -0,0 +1,406 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! A thread-safe pool of connections to one database file.
+//!
+//! The pool is meant for WAL mode: it hands out at most one writer
+//! connection at a time, so writers queue in the pool rather than on
+//! SQLite's lock, and up to a fixed number of read-only reader
+//! connections that can run alongside it.
+
+use std::cmp;
+use std::i64;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+use time;
+
+use database::Database;
+use pragma::JournalWal;
+use types::*;
+use util::deadline_after;
+
+/// Prepares each new connection, e.g. by setting pragmas or registering
+/// functions and collations, see `PoolConfig::with_init`.
+pub type ConnectionInit = Box<for<'a> Fn<(&'a Database,), SqliteResult<()>> + Send + Sync>;
+
+/// SQLite's default `wal_autocheckpoint`, restored when a connection
+/// comes back in case the borrower set a WAL hook.
+static DEFAULT_WAL_AUTOCHECKPOINT: int = 1000;
+
+#[cfg(feature = "preupdate_hook")]
+#[cfg(not(feature = "loadable_extension"))]
+fn clear_preupdate_hook(database: &Database) {
+    database.clear_preupdate_hook();
+}
+
+#[cfg(any(not(feature = "preupdate_hook"), feature = "loadable_extension"))]
+fn clear_preupdate_hook(_database: &Database) {}
+
+/// How a `Pool` opens and hands out connections.
+pub struct PoolConfig {
+    /// The database file.
+    pub path: String,
+    /// The most reader connections open at once.
+    pub readers: uint,
+    /// How long `reader` and `writer` wait for a free connection, and the
+    /// busy timeout of every connection.
+    pub timeout: Duration,
+    /// Run on every new connection after the pool has configured it.
+    pub init: Option<ConnectionInit>,
+}
+
+impl PoolConfig {
+    /// Four readers, a five second timeout and no init.
+    pub fn new(path: &str) -> PoolConfig {
+        PoolConfig {
+            path: path.to_string(),
+            readers: 4,
+            timeout: Duration::seconds(5),
+            init: None,
+        }
+    }
+
+    /// Sets the closure run on every new connection, which may capture
+    /// settings to apply.
+    pub fn with_init<F>(self, init: F) -> PoolConfig
+            where F: for<'a> Fn<(&'a Database,), SqliteResult<()>> + Send + Sync {
+        PoolConfig { init: Some(box init as ConnectionInit), ..self }
+    }
+}
+
+struct SlotState {
+    idle: Vec<Database>,
+    open: uint,
+}
+
+struct Slot {
+    state: Mutex<SlotState>,
+    cvar: Condvar,
+    max: uint,
+}
+
+struct PoolInner {
+    config: PoolConfig,
+    readers: Slot,
+    writer: Slot,
+}
+
+/// A pool of one writer and several reader connections. Cloning the pool
+/// is cheap and shares the connections.
+#[deriving(Clone)]
+pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+/// A connection checked out of a `Pool`. It is returned when dropped,
+/// after removing any hooks, handlers, authorizer or trace callback set
+/// on it and rolling back any transaction left open.
+pub struct PooledConnection {
+    inner: Arc<PoolInner>,
+    writer: bool,
+    database: Option<Database>,
+}
+
+impl Deref<Database> for PooledConnection {
+    fn deref<'a>(&'a self) -> &'a Database {
+        self.database.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        let database = self.database.take().unwrap();
+        let slot = self.inner.slot(self.writer);
+        if self.inner.reset(&database, self.writer).is_err() {
+            slot.release();
+            return;
+        }
+        slot.state.lock().idle.push(database);
+        slot.cvar.notify_one();
+    }
+}
+
+impl Slot {
+    fn new(max: uint) -> Slot {
+        Slot {
+            state: Mutex::new(SlotState { idle: Vec::new(), open: 0 }),
+            cvar: Condvar::new(),
+            max: max,
+        }
+    }
+
+    /// Takes an idle connection, or reserves room for a new one (`None`).
+    fn reserve(&self, timeout: Duration) -> SqliteResult<Option<Database>> {
+        let deadline = deadline_after(timeout);
+        let mut state = self.state.lock();
+        loop {
+            match state.idle.pop() {
+                Some(database) => return Ok(Some(database)),
+                None => {}
+            }
+            if state.open < self.max {
+                state.open += 1;
+                return Ok(None);
+            }
+            let now = time::precise_time_ns();
+            if now >= deadline {
+                return Err(SQLITE_BUSY);
+            }
+            let wait = cmp::min(deadline - now, i64::MAX as u64);
+            self.cvar.wait_timeout(&state, Duration::nanoseconds(wait as i64));
+        }
+    }
+
+    /// Gives up a reservation or a broken connection.
+    fn release(&self) {
+        self.state.lock().open -= 1;
+        self.cvar.notify_one();
+    }
+}
+
+impl PoolInner {
+    fn slot(&self, writer: bool) -> &Slot {
+        if writer { &self.writer } else { &self.readers }
+    }
+
+    fn open(&self, writer: bool) -> SqliteResult<Database> {
+        let database = try!(Database::new(self.config.path.as_slice()));
+        try!(database.set_busy_timeout(self.config.timeout));
+        if writer {
+            if try!(database.set_journal_mode(None, JournalWal)) != JournalWal {
+                return Err(SQLITE_MISUSE);
+            }
+        } else {
+            try!(database.exec("PRAGMA query_only = 1"));
+        }
+        match self.config.init {
+            Some(ref init) => try!(init.call((&database,))),
+            None => {}
+        }
+        Ok(database)
+    }
+
+    /// Undoes what a borrower may have left behind, so the next one gets
+    /// the connection as `open` configured it. Callbacks go first so none
+    /// of them sees the rollback.
+    fn reset(&self, database: &Database, writer: bool) -> SqliteResult<()> {
+        database.clear_progress_handler();
+        database.clear_update_hook();
+        database.clear_commit_hook();
+        database.clear_rollback_hook();
+        clear_preupdate_hook(database);
+        try!(database.clear_trace());
+        try!(database.clear_authorizer());
+        try!(database.set_busy_timeout(self.config.timeout));
+        try!(database.wal_autocheckpoint(DEFAULT_WAL_AUTOCHECKPOINT));
+        if !database.is_autocommit() {
+            try!(database.exec("ROLLBACK"));
+        }
+        if !writer {
+            // The borrower may have turned it off.
+            try!(database.exec("PRAGMA query_only = 1"));
+        }
+        Ok(())
+    }
+
+    fn healthy(&self, database: &Database) -> bool {
+        match database.prepare("SELECT 1") {
+            Ok(cursor) => cursor.step() == Ok(SQLITE_ROW),
+            Err(_) => false
+        }
+    }
+}
+
+impl Pool {
+    /// Creates a pool. The writer connection is opened right away, which
+    /// switches the database to WAL mode; readers are opened on demand.
+    /// Fails with `SQLITE_MISUSE` if the database cannot use WAL mode,
+    /// e.g. because it is in memory.
+    pub fn new(config: PoolConfig) -> SqliteResult<Pool> {
+        let readers = config.readers;
+        let pool = Pool {
+            inner: Arc::new(PoolInner {
+                config: config,
+                readers: Slot::new(readers),
+                writer: Slot::new(1),
+            })
+        };
+        drop(try!(pool.writer()));
+        Ok(pool)
+    }
+
+    /// Checks out a read-only connection, waiting up to the configured
+    /// timeout for one to become free. Fails with `SQLITE_BUSY` on timeout.
+    pub fn reader(&self) -> SqliteResult<PooledConnection> {
+        self.checkout(false)
+    }
+
+    /// Checks out the writer connection, waiting up to the configured
+    /// timeout for it to become free. Fails with `SQLITE_BUSY` on timeout.
+    pub fn writer(&self) -> SqliteResult<PooledConnection> {
+        self.checkout(true)
+    }
+
+    fn checkout(&self, writer: bool) -> SqliteResult<PooledConnection> {
+        let inner = &self.inner;
+        let slot = inner.slot(writer);
+        // a connection that fails its health check is dropped and replaced,
+        // keeping its place in the count
+        let reused = match try!(slot.reserve(inner.config.timeout)) {
+            Some(database) => if inner.healthy(&database) { Some(database) } else { None },
+            None => None
+        };
+        let database = match reused {
+            Some(database) => database,
+            None => match inner.open(writer) {
+                Ok(database) => database,
+                Err(e) => {
+                    slot.release();
+                    return Err(e);
+                }
+            }
+        };
+        Ok(PooledConnection { inner: inner.clone(), writer: writer, database: Some(database) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use authorizer::*;
+    use database::Database;
+    use std::io::TempDir;
+    use std::time::Duration;
+    use types::*;
+    use super::*;
+
+    fn config(dir: &TempDir) -> PoolConfig {
+        let path = dir.path().join("pool.db");
+        let mut config = PoolConfig::new(path.as_str().unwrap());
+        config.readers = 2;
+        config.timeout = Duration::milliseconds(50);
+        config
+    }
+
+    #[test]
+    fn readers_are_limited_and_read_only() {
+        let dir = TempDir::new("rustsqlite").unwrap();
+        let pool = Pool::new(config(&dir)).unwrap();
+        pool.writer().unwrap().exec("CREATE TABLE test (v); INSERT INTO test VALUES (1);").unwrap();
+
+        let r1 = pool.reader().unwrap();
+        let r2 = pool.reader().unwrap();
+        assert_eq!(pool.reader().err(), Some(SQLITE_BUSY));
+        assert_eq!(r1.exec("INSERT INTO test VALUES (2)"), Err(SQLITE_READONLY));
+        drop(r2);
+        let r3 = pool.reader().unwrap();
+        let cursor = r3.prepare("SELECT count(*) FROM test").unwrap();
+        assert_eq!(cursor.step(), Ok(SQLITE_ROW));
+        assert_eq!(cursor.get_int(0), 1);
+    }
+
+    #[test]
+    fn single_writer_across_threads() {
+        let dir = TempDir::new("rustsqlite").unwrap();
+        let pool = Pool::new(config(&dir)).unwrap();
+        pool.writer().unwrap().exec("CREATE TABLE test (v)").unwrap();
+
+        let writer = pool.writer().unwrap();
+        writer.exec("BEGIN; INSERT INTO test VALUES (1);").unwrap();
+        let (tx, rx) = channel();
+        let other = pool.clone();
+        spawn(proc() {
+            tx.send(other.writer().err());
+        });
+        assert_eq!(rx.recv(), Some(SQLITE_BUSY));
+
+        // the open transaction is rolled back when the writer is returned
+        drop(writer);
+        let writer = pool.writer().unwrap();
+        assert!(writer.is_autocommit());
+        let cursor = writer.prepare("SELECT count(*) FROM test").unwrap();
+        assert_eq!(cursor.step(), Ok(SQLITE_ROW));
+        assert_eq!(cursor.get_int(0), 0);
+    }
+
+    #[test]
+    fn init_runs_on_new_connections() {
+        let cache_size = -4000i;
+        let dir = TempDir::new("rustsqlite").unwrap();
+        let config = config(&dir).with_init(move |&: database: &Database| {
+            try!(database.exec("PRAGMA foreign_keys = ON"));
+            database.exec(format!("PRAGMA cache_size = {}", cache_size).as_slice())
+        });
+        let pool = Pool::new(config).unwrap();
+        assert_eq!(pool.reader().unwrap().foreign_keys(), Ok(true));
+        assert_eq!(pool.writer().unwrap().foreign_keys(), Ok(true));
+        assert_eq!(pool.reader().unwrap().cache_size(None), Ok(cache_size as i64));
+    }
+
+    #[test]
+    fn readers_stay_read_only() {
+        let dir = TempDir::new("rustsqlite").unwrap();
+        let pool = Pool::new(config(&dir)).unwrap();
+        pool.writer().unwrap().exec("CREATE TABLE test (v)").unwrap();
+        {
+            let reader = pool.reader().unwrap();
+            reader.exec("PRAGMA query_only = 0").unwrap();
+        }
+        let reader = pool.reader().unwrap();
+        assert_eq!(reader.exec("INSERT INTO test VALUES (1)"), Err(SQLITE_READONLY));
+    }
+
+    #[test]
+    fn needs_wal_mode() {
+        assert_eq!(Pool::new(PoolConfig::new(":memory:")).err(), Some(SQLITE_MISUSE));
+    }
+
+    #[test]
+    fn returned_connections_are_reset() {
+        let dir = TempDir::new("rustsqlite").unwrap();
+        let pool = Pool::new(config(&dir)).unwrap();
+        pool.writer().unwrap().exec("CREATE TABLE test (v)").unwrap();
+
+        let (tx, rx) = channel();
+        {
+            let writer = pool.writer().unwrap();
+            writer.set_update_hook(move |&mut: _op, _db: String, _table: String, rowid: i64| tx.send(rowid));
+            writer.set_rollback_hook(move |&mut:| fail!("rollback hook survived the return"));
+            writer.exec("BEGIN").unwrap();
+            assert_eq!(writer.set_authorizer(move |&mut: _ctx| AuthDeny), Ok(()));
+        }
+
+        // the hooks were dropped, closing the channel, and the new borrower
+        // is not denied by the old authorizer
+        let writer = pool.writer().unwrap();
+        writer.exec("INSERT INTO test VALUES (1)").unwrap();
+        assert_eq!(rx.iter().count(), 0);
+    }
+}
@@ -0,0 +1,372 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Reading and setting PRAGMAs.
+//!
+//! The common settings have typed getters and setters on `Database`, such
+//! as `journal_mode` and `set_synchronous`. `pragma_query`,
+//! `pragma_value` and `pragma_update` reach the rest by name. Names must
+//! be plain identifiers and values are written out as SQL literals, since
+//! PRAGMA arguments cannot be bound as parameters.
+//! See http://www.sqlite.org/pragma.html
+
+use std::ascii::StrAsciiExt;
+
+use cursor::Cursor;
+use database::Database;
+use types::*;
+
+/// A journal mode, see `Database::set_journal_mode`.
+/// See http://www.sqlite.org/pragma.html#pragma_journal_mode
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum JournalMode {
+    JournalDelete,
+    JournalTruncate,
+    JournalPersist,
+    JournalMemory,
+    JournalWal,
+    JournalOff,
+}
+
+impl JournalMode {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            JournalDelete   => "DELETE",
+            JournalTruncate => "TRUNCATE",
+            JournalPersist  => "PERSIST",
+            JournalMemory   => "MEMORY",
+            JournalWal      => "WAL",
+            JournalOff      => "OFF",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<JournalMode> {
+        match s.to_ascii_upper().as_slice() {
+            "DELETE"   => Some(JournalDelete),
+            "TRUNCATE" => Some(JournalTruncate),
+            "PERSIST"  => Some(JournalPersist),
+            "MEMORY"   => Some(JournalMemory),
+            "WAL"      => Some(JournalWal),
+            "OFF"      => Some(JournalOff),
+            _ => None
+        }
+    }
+}
+
+/// A synchronous level, see `Database::set_synchronous`.
+/// See http://www.sqlite.org/pragma.html#pragma_synchronous
+#[deriving(PartialEq, Eq, Show, Clone, FromPrimitive)]
+pub enum Synchronous {
+    SyncOff    = 0,
+    SyncNormal = 1,
+    SyncFull   = 2,
+    SyncExtra  = 3,
+}
+
+/// Quotes `name` for use as an SQL identifier.
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace("\"", "\"\""))
+}
+
+/// Renders `value` as an SQL literal. PRAGMA arguments cannot be bound
+/// as parameters, so they have to be spelled out. SQL has no literal for
+/// NaN or infinity, so those fail with `SQLITE_MISUSE`.
+pub fn sql_literal(value: &BindArg) -> SqliteResult<String> {
+    Ok(match *value {
+        Text(ref v) => format!("'{}'", v.replace("'", "''")),
+        StaticText(v) => format!("'{}'", v.replace("'", "''")),
+        Float64(v) if v.is_nan() || v.is_infinite() => return Err(SQLITE_MISUSE),
+        Float64(v) => v.to_string(),
+        Integer(v) => v.to_string(),
+        Integer64(v) => v.to_string(),
+        Blob(ref v) => {
+            let mut s = "x'".to_string();
+            for b in v.iter() {
+                s.push_str(format!("{:02x}", *b).as_slice());
+            }
+            s.push_char('\'');
+            s
+        }
+        Null => "NULL".to_string(),
+    })
+}
+
+/// Whether `name` can be spliced into a PRAGMA statement as-is, i.e.
+/// matches `[A-Za-z_][A-Za-z0-9_]*`.
+fn is_pragma_name(name: &str) -> bool {
+    fn is_start(c: char) -> bool {
+        (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_'
+    }
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if is_start(c) => chars.all(|c| is_start(c) || (c >= '0' && c <= '9')),
+        _ => false
+    }
+}
+
+/// Fails with `SQLITE_MISUSE` unless `name` is a plain identifier.
+fn pragma_sql(schema: Option<&str>, name: &str, value: Option<&BindArg>) -> SqliteResult<String> {
+    if !is_pragma_name(name) {
+        return Err(SQLITE_MISUSE);
+    }
+    let mut sql = "PRAGMA ".to_string();
+    match schema {
+        Some(schema) => {
+            sql.push_str(quote_identifier(schema).as_slice());
+            sql.push_char('.');
+        }
+        None => {}
+    }
+    sql.push_str(name);
+    match value {
+        Some(value) => {
+            sql.push_str(" = ");
+            sql.push_str(try!(sql_literal(value)).as_slice());
+        }
+        None => {}
+    }
+    Ok(sql)
+}
+
+fn column_value(cursor: &Cursor, i: int) -> BindArg {
+    match cursor.get_column_type(i) {
+        SQLITE_INTEGER => Integer64(cursor.get_i64(i)),
+        SQLITE_FLOAT   => Float64(cursor.get_f64(i)),
+        SQLITE_TEXT    => Text(cursor.get_text(i)),
+        SQLITE_BLOB    => Blob(cursor.get_blob(i)),
+        SQLITE_NULL    => Null,
+    }
+}
+
+fn as_i64(value: BindArg) -> SqliteResult<i64> {
+    match value {
+        Integer(v) => Ok(v as i64),
+        Integer64(v) => Ok(v),
+        _ => Err(SQLITE_MISMATCH)
+    }
+}
+
+fn as_text(value: BindArg) -> SqliteResult<String> {
+    match value {
+        Text(v) => Ok(v),
+        _ => Err(SQLITE_MISMATCH)
+    }
+}
+
+impl Database {
+    fn pragma_rows(&self, sql: &str) -> SqliteResult<Vec<Vec<(String, BindArg)>>> {
+        debug!("`Database.pragma()`: {}", sql);
+        let cursor = try!(self.prepare(sql));
+        let mut rows = Vec::new();
+        loop {
+            match try!(cursor.step()) {
+                SQLITE_ROW => {
+                    let row = range(0, cursor.get_column_count()).map(|i| {
+                        (cursor.get_column_name(i), column_value(&cursor, i))
+                    }).collect();
+                    rows.push(row);
+                }
+                SQLITE_DONE => return Ok(rows)
+            }
+        }
+    }
+
+    fn pragma_value(&self, schema: Option<&str>, name: &str, value: Option<&BindArg>) -> SqliteResult<BindArg> {
+        let rows = try!(self.pragma_rows(try!(pragma_sql(schema, name, value)).as_slice()));
+        match rows.move_iter().next().and_then(|row| row.move_iter().next()) {
+            Some((_, v)) => Ok(v),
+            None => Ok(Null)
+        }
+    }
+
+    /// Runs `PRAGMA schema.name` and returns every row as a list of
+    /// (column name, value) pairs. `schema` defaults to "main".
+    /// See http://www.sqlite.org/pragma.html
+    pub fn pragma_query(&self, schema: Option<&str>, name: &str) -> SqliteResult<Vec<Vec<(String, BindArg)>>> {
+        self.pragma_rows(try!(pragma_sql(schema, name, None)).as_slice())
+    }
+
+    /// Runs `PRAGMA schema.name = value` and returns the rows it produces,
+    /// if any.
+    /// See http://www.sqlite.org/pragma.html
+    pub fn pragma_update(&self, schema: Option<&str>, name: &str, value: &BindArg) -> SqliteResult<Vec<Vec<(String, BindArg)>>> {
+        self.pragma_rows(try!(pragma_sql(schema, name, Some(value))).as_slice())
+    }
+
+    /// See http://www.sqlite.org/pragma.html#pragma_journal_mode
+    pub fn journal_mode(&self, schema: Option<&str>) -> SqliteResult<JournalMode> {
+        let mode = try!(as_text(try!(self.pragma_value(schema, "journal_mode", None))));
+        JournalMode::from_str(mode.as_slice()).ok_or(SQLITE_MISMATCH)
+    }
+
+    /// Sets the journal mode and returns the mode now in effect, which
+    /// differs from `mode` if SQLite could not switch (e.g. WAL for an
+    /// in-memory database).
+    /// See http://www.sqlite.org/pragma.html#pragma_journal_mode
+    pub fn set_journal_mode(&self, schema: Option<&str>, mode: JournalMode) -> SqliteResult<JournalMode> {
+        let mode = try!(as_text(try!(self.pragma_value(schema, "journal_mode", Some(&StaticText(mode.as_str()))))));
+        JournalMode::from_str(mode.as_slice()).ok_or(SQLITE_MISMATCH)
+    }
+
+    /// See http://www.sqlite.org/pragma.html#pragma_synchronous
+    pub fn synchronous(&self, schema: Option<&str>) -> SqliteResult<Synchronous> {
+        let level = try!(as_i64(try!(self.pragma_value(schema, "synchronous", None))));
+        FromPrimitive::from_i64(level).ok_or(SQLITE_MISMATCH)
+    }
+
+    /// See http://www.sqlite.org/pragma.html#pragma_synchronous
+    pub fn set_synchronous(&self, schema: Option<&str>, level: Synchronous) -> SqliteResult<()> {
+        self.pragma_value(schema, "synchronous", Some(&Integer(level as int))).map(|_| ())
+    }
+
+    /// See http://www.sqlite.org/pragma.html#pragma_foreign_keys
+    pub fn foreign_keys(&self) -> SqliteResult<bool> {
+        Ok(try!(as_i64(try!(self.pragma_value(None, "foreign_keys", None)))) != 0)
+    }
+
+    /// Has no effect inside a transaction.
+    /// See http://www.sqlite.org/pragma.html#pragma_foreign_keys
+    pub fn set_foreign_keys(&self, enabled: bool) -> SqliteResult<()> {
+        self.pragma_value(None, "foreign_keys", Some(&Integer(enabled as int))).map(|_| ())
+    }
+
+    /// Returns the suggested number of cache pages, or the cache size in
+    /// KiB if negative.
+    /// See http://www.sqlite.org/pragma.html#pragma_cache_size
+    pub fn cache_size(&self, schema: Option<&str>) -> SqliteResult<i64> {
+        as_i64(try!(self.pragma_value(schema, "cache_size", None)))
+    }
+
+    /// See http://www.sqlite.org/pragma.html#pragma_cache_size
+    pub fn set_cache_size(&self, schema: Option<&str>, size: i64) -> SqliteResult<()> {
+        self.pragma_value(schema, "cache_size", Some(&Integer64(size))).map(|_| ())
+    }
+
+    /// See http://www.sqlite.org/pragma.html#pragma_user_version
+    pub fn user_version(&self, schema: Option<&str>) -> SqliteResult<i64> {
+        as_i64(try!(self.pragma_value(schema, "user_version", None)))
+    }
+
+    /// See http://www.sqlite.org/pragma.html#pragma_user_version
+    pub fn set_user_version(&self, schema: Option<&str>, version: i64) -> SqliteResult<()> {
+        self.pragma_value(schema, "user_version", Some(&Integer64(version))).map(|_| ())
+    }
+
+    /// See http://www.sqlite.org/pragma.html#pragma_application_id
+    pub fn application_id(&self, schema: Option<&str>) -> SqliteResult<i64> {
+        as_i64(try!(self.pragma_value(schema, "application_id", None)))
+    }
+
+    /// See http://www.sqlite.org/pragma.html#pragma_application_id
+    pub fn set_application_id(&self, schema: Option<&str>, id: i64) -> SqliteResult<()> {
+        self.pragma_value(schema, "application_id", Some(&Integer64(id))).map(|_| ())
+    }
+
+    /// See http://www.sqlite.org/pragma.html#pragma_mmap_size
+    pub fn mmap_size(&self, schema: Option<&str>) -> SqliteResult<i64> {
+        as_i64(try!(self.pragma_value(schema, "mmap_size", None)))
+    }
+
+    /// Returns the size now in effect, which is capped by the compile-time
+    /// maximum.
+    /// See http://www.sqlite.org/pragma.html#pragma_mmap_size
+    pub fn set_mmap_size(&self, schema: Option<&str>, size: i64) -> SqliteResult<i64> {
+        as_i64(try!(self.pragma_value(schema, "mmap_size", Some(&Integer64(size)))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::Database;
+    use std::io::TempDir;
+    use types::*;
+    use super::*;
+
+    #[test]
+    fn user_version_and_application_id() {
+        let database = Database::new(":memory:").unwrap();
+        assert_eq!(database.user_version(None), Ok(0));
+        assert_eq!(database.set_user_version(None, 7), Ok(()));
+        assert_eq!(database.user_version(None), Ok(7));
+        assert_eq!(database.set_application_id(None, 0x5153), Ok(()));
+        assert_eq!(database.application_id(None), Ok(0x5153));
+    }
+
+    #[test]
+    fn journal_mode_and_synchronous() {
+        let dir = TempDir::new("rustsqlite").unwrap();
+        let path = dir.path().join("pragma.db");
+        let database = Database::new(path.as_str().unwrap()).unwrap();
+        assert_eq!(database.journal_mode(None), Ok(JournalDelete));
+        assert_eq!(database.set_journal_mode(None, JournalWal), Ok(JournalWal));
+        assert_eq!(database.set_synchronous(None, SyncNormal), Ok(()));
+        assert_eq!(database.synchronous(None), Ok(SyncNormal));
+
+        let memory = Database::new(":memory:").unwrap();
+        assert_eq!(memory.set_journal_mode(None, JournalWal), Ok(JournalMemory));
+    }
+
+    #[test]
+    fn foreign_keys_and_cache_size() {
+        let database = Database::new(":memory:").unwrap();
+        assert_eq!(database.set_foreign_keys(true), Ok(()));
+        assert_eq!(database.foreign_keys(), Ok(true));
+        assert_eq!(database.set_cache_size(None, -4000), Ok(()));
+        assert_eq!(database.cache_size(None), Ok(-4000));
+    }
+
+    #[test]
+    fn schema_qualified() {
+        let database = Database::new(":memory:").unwrap();
+        database.exec("ATTACH ':memory:' AS \"my aux\"").unwrap();
+        assert_eq!(database.set_user_version(Some("my aux"), 3), Ok(()));
+        assert_eq!(database.user_version(Some("my aux")), Ok(3));
+        assert_eq!(database.user_version(None), Ok(0));
+
+        let rows = database.pragma_query(None, "database_list").unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1][1], ("name".to_string(), Text("my aux".to_string())));
+    }
+
+    #[test]
+    fn rejects_odd_names() {
+        let database = Database::new(":memory:").unwrap();
+        database.exec("CREATE TABLE t (x)").unwrap();
+        assert_eq!(database.pragma_query(None, "user_version; DROP TABLE t"), Err(SQLITE_MISUSE));
+        assert_eq!(database.pragma_update(None, "1cache", &Integer(1)), Err(SQLITE_MISUSE));
+        assert_eq!(database.pragma_query(None, ""), Err(SQLITE_MISUSE));
+        assert_eq!(database.pragma_update(None, "cache_size", &Float64(0.0 / 0.0)), Err(SQLITE_MISUSE));
+        assert_eq!(database.pragma_update(None, "cache_size", &Float64(-1.0 / 0.0)), Err(SQLITE_MISUSE));
+        assert!(database.pragma_query(None, "table_info(t)").is_err());
+        assert_eq!(database.pragma_query(None, "_unknown_2").map(|rows| rows.len()), Ok(0));
+        assert!(database.exec("SELECT x FROM t").is_ok());
+    }
+}
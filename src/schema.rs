@@ -0,0 +1,332 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Describing the tables, views, indexes and triggers of a database.
+
+use cursor::Cursor;
+use database::Database;
+use pragma::quote_identifier;
+use types::*;
+
+/// The objects of one attached database.
+#[deriving(PartialEq, Show, Clone)]
+pub struct DatabaseSchema {
+    /// The schema name, e.g. "main" or "temp".
+    pub name: String,
+    /// The file name, empty for in-memory and temporary databases.
+    pub file: String,
+    pub tables: Vec<Table>,
+    pub views: Vec<View>,
+    pub indexes: Vec<Index>,
+    pub triggers: Vec<Trigger>,
+}
+
+/// A table, including internal ones such as `sqlite_sequence`.
+#[deriving(PartialEq, Show, Clone)]
+pub struct Table {
+    pub name: String,
+    /// The `CREATE` statement, if any.
+    pub sql: Option<String>,
+    pub columns: Vec<Column>,
+    pub foreign_keys: Vec<ForeignKey>,
+}
+
+/// A view.
+#[deriving(PartialEq, Show, Clone)]
+pub struct View {
+    pub name: String,
+    pub sql: Option<String>,
+    pub columns: Vec<Column>,
+}
+
+/// Whether a column is stored, hidden or generated.
+#[deriving(PartialEq, Eq, Show, Clone, FromPrimitive)]
+pub enum ColumnKind {
+    NormalColumn     = 0,
+    /// A hidden column of a virtual table.
+    HiddenColumn     = 1,
+    GeneratedVirtual = 2,
+    GeneratedStored  = 3,
+}
+
+/// A column of a table or view.
+/// See http://www.sqlite.org/pragma.html#pragma_table_info
+#[deriving(PartialEq, Show, Clone)]
+pub struct Column {
+    pub cid: int,
+    pub name: String,
+    /// The declared type, empty if none was given.
+    pub decl_type: String,
+    pub not_null: bool,
+    /// The default value as SQL text.
+    pub default: Option<String>,
+    /// The 1-based position of the column in the primary key, or 0.
+    pub primary_key: int,
+    pub kind: ColumnKind,
+}
+
+/// A foreign key constraint, with one (from, to) pair per column. `to` is
+/// `None` when the constraint refers to the parent's primary key.
+/// See http://www.sqlite.org/pragma.html#pragma_foreign_key_list
+#[deriving(PartialEq, Show, Clone)]
+pub struct ForeignKey {
+    pub id: int,
+    pub table: String,
+    pub columns: Vec<(String, Option<String>)>,
+    pub on_update: String,
+    pub on_delete: String,
+}
+
+/// An index, including those created implicitly for UNIQUE and PRIMARY
+/// KEY constraints.
+/// See http://www.sqlite.org/pragma.html#pragma_index_list
+#[deriving(PartialEq, Show, Clone)]
+pub struct Index {
+    pub name: String,
+    pub table: String,
+    pub sql: Option<String>,
+    pub unique: bool,
+    /// "c" for CREATE INDEX, "u" for UNIQUE, "pk" for PRIMARY KEY.
+    pub origin: String,
+    pub partial: bool,
+    pub columns: Vec<IndexColumn>,
+}
+
+/// A column of an index.
+/// See http://www.sqlite.org/pragma.html#pragma_index_xinfo
+#[deriving(PartialEq, Show, Clone)]
+pub struct IndexColumn {
+    pub seqno: int,
+    /// The table column, -1 for the rowid and -2 for an expression.
+    pub cid: int,
+    /// `None` for the rowid and expressions.
+    pub name: Option<String>,
+    pub desc: bool,
+    pub collation: String,
+    /// `false` for the auxiliary columns stored after the key.
+    pub key: bool,
+}
+
+/// A trigger.
+#[deriving(PartialEq, Show, Clone)]
+pub struct Trigger {
+    pub name: String,
+    pub table: String,
+    pub sql: Option<String>,
+}
+
+fn opt_text(cursor: &Cursor, i: int) -> Option<String> {
+    match cursor.get_column_type(i) {
+        SQLITE_NULL => None,
+        _ => Some(cursor.get_text(i))
+    }
+}
+
+fn collect<T>(cursor: &Cursor, f: |&Cursor| -> T) -> SqliteResult<Vec<T>> {
+    let mut out = Vec::new();
+    loop {
+        match try!(cursor.step()) {
+            SQLITE_ROW => out.push(f(cursor)),
+            SQLITE_DONE => return Ok(out)
+        }
+    }
+}
+
+impl Database {
+    /// Describes every attached database, "main" first.
+    pub fn schema(&self) -> SqliteResult<Vec<DatabaseSchema>> {
+        let cursor = try!(self.prepare("SELECT name, file FROM pragma_database_list ORDER BY seq"));
+        let names = try!(collect(&cursor, |c| (c.get_text(0), c.get_text(1))));
+        let mut out = Vec::new();
+        for (name, file) in names.move_iter() {
+            out.push(try!(self.database_schema(name, file)));
+        }
+        Ok(out)
+    }
+
+    fn database_schema(&self, name: String, file: String) -> SqliteResult<DatabaseSchema> {
+        let sql = format!("SELECT type, name, tbl_name, sql FROM {}.sqlite_schema ORDER BY name",
+                          quote_identifier(name.as_slice()));
+        let cursor = try!(self.prepare(sql.as_slice()));
+        let objects = try!(collect(&cursor, |c| {
+            (c.get_text(0), c.get_text(1), c.get_text(2), opt_text(c, 3))
+        }));
+
+        let mut schema = DatabaseSchema {
+            name: name, file: file,
+            tables: Vec::new(), views: Vec::new(), indexes: Vec::new(), triggers: Vec::new(),
+        };
+        for (kind, obj, table, sql) in objects.move_iter() {
+            match kind.as_slice() {
+                "table" => {
+                    let columns = try!(self.columns(schema.name.as_slice(), obj.as_slice()));
+                    let foreign_keys = try!(self.table_foreign_keys(schema.name.as_slice(), obj.as_slice()));
+                    schema.tables.push(Table { name: obj, sql: sql, columns: columns, foreign_keys: foreign_keys });
+                }
+                "view" => {
+                    let columns = try!(self.columns(schema.name.as_slice(), obj.as_slice()));
+                    schema.views.push(View { name: obj, sql: sql, columns: columns });
+                }
+                "index" => {
+                    let index = try!(self.index(schema.name.as_slice(), obj, table, sql));
+                    schema.indexes.push(index);
+                }
+                "trigger" => schema.triggers.push(Trigger { name: obj, table: table, sql: sql }),
+                _ => {}
+            }
+        }
+        Ok(schema)
+    }
+
+    fn columns(&self, schema: &str, table: &str) -> SqliteResult<Vec<Column>> {
+        let cursor = try!(self.prepare(
+            "SELECT cid, name, type, \"notnull\", dflt_value, pk, hidden FROM pragma_table_xinfo(?1, ?2)"));
+        try!(cursor.bind_params(&[Text(table.to_string()), Text(schema.to_string())]));
+        collect(&cursor, |c| Column {
+            cid: c.get_int(0),
+            name: c.get_text(1),
+            decl_type: c.get_text(2),
+            not_null: c.get_int(3) != 0,
+            default: opt_text(c, 4),
+            primary_key: c.get_int(5),
+            kind: FromPrimitive::from_int(c.get_int(6)).unwrap_or(NormalColumn),
+        })
+    }
+
+    fn table_foreign_keys(&self, schema: &str, table: &str) -> SqliteResult<Vec<ForeignKey>> {
+        let cursor = try!(self.prepare(
+            "SELECT id, \"table\", \"from\", \"to\", on_update, on_delete
+             FROM pragma_foreign_key_list(?1, ?2) ORDER BY id, seq"));
+        try!(cursor.bind_params(&[Text(table.to_string()), Text(schema.to_string())]));
+        let rows = try!(collect(&cursor, |c| {
+            (c.get_int(0), c.get_text(1), c.get_text(2), opt_text(c, 3), c.get_text(4), c.get_text(5))
+        }));
+
+        let mut out: Vec<ForeignKey> = Vec::new();
+        for (id, parent, from, to, on_update, on_delete) in rows.move_iter() {
+            if out.last().map_or(true, |fk| fk.id != id) {
+                out.push(ForeignKey {
+                    id: id, table: parent, columns: Vec::new(),
+                    on_update: on_update, on_delete: on_delete,
+                });
+            }
+            out.mut_last().unwrap().columns.push((from, to));
+        }
+        Ok(out)
+    }
+
+    fn index(&self, schema: &str, name: String, table: String, sql: Option<String>) -> SqliteResult<Index> {
+        let cursor = try!(self.prepare(
+            "SELECT \"unique\", origin, partial FROM pragma_index_list(?1, ?2) WHERE name = ?3"));
+        try!(cursor.bind_params(&[Text(table.clone()), Text(schema.to_string()), Text(name.clone())]));
+        let (unique, origin, partial) = match try!(collect(&cursor, |c| {
+            (c.get_int(0) != 0, c.get_text(1), c.get_int(2) != 0)
+        })).move_iter().next() {
+            Some(info) => info,
+            None => return Err(SQLITE_NOTFOUND)
+        };
+
+        let cursor = try!(self.prepare(
+            "SELECT seqno, cid, name, \"desc\", coll, key FROM pragma_index_xinfo(?1, ?2)"));
+        try!(cursor.bind_params(&[Text(name.clone()), Text(schema.to_string())]));
+        let columns = try!(collect(&cursor, |c| IndexColumn {
+            seqno: c.get_int(0),
+            cid: c.get_int(1),
+            name: opt_text(c, 2),
+            desc: c.get_int(3) != 0,
+            collation: c.get_text(4),
+            key: c.get_int(5) != 0,
+        }));
+
+        Ok(Index {
+            name: name, table: table, sql: sql,
+            unique: unique, origin: origin, partial: partial,
+            columns: columns,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::Database;
+    use super::*;
+
+    #[test]
+    fn describe_schema() {
+        let database = Database::new(":memory:").unwrap();
+        database.exec(
+            "CREATE TABLE parent (id INTEGER PRIMARY KEY, code TEXT NOT NULL UNIQUE);
+             CREATE TABLE child (
+                 id INTEGER PRIMARY KEY,
+                 parent_id INTEGER REFERENCES parent (id) ON DELETE CASCADE,
+                 qty INT DEFAULT 1,
+                 double_qty INT GENERATED ALWAYS AS (qty * 2) VIRTUAL
+             );
+             CREATE INDEX child_qty ON child (qty DESC) WHERE qty > 0;
+             CREATE VIEW totals AS SELECT parent_id, sum(qty) AS total FROM child GROUP BY parent_id;
+             CREATE TRIGGER child_ins AFTER INSERT ON child BEGIN SELECT 1; END;
+             ATTACH ':memory:' AS aux;
+             CREATE TABLE aux.other (x);").unwrap();
+
+        let schemas = database.schema().unwrap();
+        assert_eq!(schemas.iter().map(|s| s.name.clone()).collect::<Vec<String>>(),
+                   vec!("main".to_string(), "aux".to_string()));
+        let main = &schemas[0];
+
+        let names = main.tables.iter().map(|t| t.name.clone()).collect::<Vec<String>>();
+        assert_eq!(names, vec!("child".to_string(), "parent".to_string()));
+        let child = &main.tables[0];
+        assert_eq!(child.columns.len(), 4);
+        assert_eq!(child.columns[0].primary_key, 1);
+        assert_eq!(child.columns[2].decl_type.as_slice(), "INT");
+        assert_eq!(child.columns[2].default, Some("1".to_string()));
+        assert_eq!(child.columns[3].kind, GeneratedVirtual);
+        assert_eq!(child.foreign_keys.len(), 1);
+        assert_eq!(child.foreign_keys[0].table.as_slice(), "parent");
+        assert_eq!(child.foreign_keys[0].columns, vec!(("parent_id".to_string(), Some("id".to_string()))));
+        assert_eq!(child.foreign_keys[0].on_delete.as_slice(), "CASCADE");
+        assert!(main.tables[1].columns[1].not_null);
+
+        let index = main.indexes.iter().find(|i| i.name.as_slice() == "child_qty").unwrap();
+        assert!(index.partial);
+        assert!(!index.unique);
+        assert_eq!(index.columns[0].name, Some("qty".to_string()));
+        assert!(index.columns[0].desc);
+        let unique = main.indexes.iter().find(|i| i.table.as_slice() == "parent").unwrap();
+        assert!(unique.unique);
+        assert_eq!(unique.origin.as_slice(), "u");
+
+        assert_eq!(main.views.len(), 1);
+        assert_eq!(main.views[0].columns.len(), 2);
+        assert_eq!(main.triggers[0].name.as_slice(), "child_ins");
+        assert_eq!(schemas[1].tables[0].name.as_slice(), "other");
+    }
+}
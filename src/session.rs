@@ -0,0 +1,541 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Recording changes to a database and replaying them elsewhere, using
+//! SQLite's session extension.
+//!
+//! A `Session` started with `Database::session` records the changes made
+//! through its connection to the tables it is attached to. Its changeset
+//! or patchset can then be inspected, inverted, combined with others and
+//! applied to another database with `Database::apply_changeset`.
+//! See http://www.sqlite.org/sessionintro.html
+
+use ffi::*;
+use libc::{c_char, c_int, c_void};
+use std::mem;
+use std::num::FromPrimitive;
+use std::ptr;
+use std::slice;
+use types::*;
+use util::*;
+
+/// Why a change could not be applied as it stands.
+/// See http://www.sqlite.org/session/c_changeset_conflict.html
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum ConflictKind {
+    /// The row to update or delete exists, but its values differ from the
+    /// old values in the change.
+    ConflictData       = 1,
+    /// The row to update or delete does not exist.
+    ConflictNotFound   = 2,
+    /// The row to insert already exists.
+    ConflictConflict   = 3,
+    /// The change violates a constraint.
+    ConflictConstraint = 4,
+}
+
+/// What to do about a conflict.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum ConflictAction {
+    /// Skip this change.
+    ConflictOmit    = 0,
+    /// Overwrite the conflicting row. Only allowed for `ConflictData` and
+    /// `ConflictConflict`; anywhere else `apply_changeset` rolls back and
+    /// fails with `SQLITE_MISUSE`.
+    ConflictReplace = 1,
+    /// Roll back every change applied so far; `apply_changeset` then fails
+    /// with `SQLITE_ABORT`.
+    ConflictAbort   = 2,
+}
+
+/// One changed row. Values that a changeset does not record (the old
+/// values of an insert, the unchanged new values of an update, most old
+/// values in a patchset) are `None`.
+#[deriving(PartialEq, Show)]
+pub struct Change {
+    pub table: String,
+    pub operation: UpdateOperation,
+    /// Whether the change was made by a trigger or foreign key action.
+    pub indirect: bool,
+    /// Which columns make up the primary key.
+    pub primary_key: Vec<bool>,
+    pub old: Vec<Option<BindArg>>,
+    pub new: Vec<Option<BindArg>>,
+}
+
+/// A change that conflicts with the database it is applied to.
+#[deriving(PartialEq, Show)]
+pub struct ChangeConflict {
+    pub kind: ConflictKind,
+    pub change: Change,
+    /// The row currently in the database, for `ConflictData` and
+    /// `ConflictConflict`; empty otherwise.
+    pub conflicting: Vec<Option<BindArg>>,
+}
+
+/// A problem found while applying a changeset.
+#[deriving(PartialEq, Show)]
+pub enum Conflict {
+    /// One change could not be applied as it stands.
+    RowConflict(ChangeConflict),
+    /// Applying every change left this many foreign key violations. Reported
+    /// once, at the end; `ConflictOmit` commits anyway.
+    ForeignKeyConflict(int),
+}
+
+/// The closure type accepted by `Database::apply_changeset`.
+pub type ConflictHandler<'a> = |&Conflict|: 'a -> ConflictAction;
+
+/// A changeset or patchset in SQLite's binary format.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub struct Changeset {
+    bytes: Vec<u8>,
+}
+
+/// Records changes made through one connection.
+/// See http://www.sqlite.org/session/session.html
+pub struct Session<'db> {
+    session: *mut session,
+    _dbh: &'db *mut dbh,
+}
+
+#[unsafe_destructor]
+impl<'db> Drop for Session<'db> {
+    fn drop(&mut self) {
+        unsafe { sqlite3session_delete(self.session) }
+    }
+}
+
+impl<'db> Session<'db> {
+    #[allow(visible_private_types)]
+    pub fn new(dbh: &'db *mut dbh, schema: &str) -> SqliteResult<Session<'db>> {
+        let mut s = ptr::mut_null();
+        let r = schema.with_c_str(|_schema| unsafe {
+            sqlite3session_create(*dbh, _schema, &mut s)
+        });
+        try!(check(r));
+        Ok(Session { session: s, _dbh: dbh })
+    }
+
+    /// Starts recording changes to `table`, or to every table if `None`.
+    /// Only tables with a primary key are recorded.
+    pub fn attach(&self, table: Option<&str>) -> SqliteResult<()> {
+        let r = match table {
+            Some(table) => table.with_c_str(|_table| unsafe {
+                sqlite3session_attach(self.session, _table)
+            }),
+            None => unsafe { sqlite3session_attach(self.session, ptr::null()) }
+        };
+        check(r)
+    }
+
+    /// Pauses or resumes recording.
+    pub fn set_enabled(&self, enabled: bool) {
+        unsafe { sqlite3session_enable(self.session, enabled as c_int); }
+    }
+
+    /// Returns whether no changes have been recorded.
+    pub fn is_empty(&self) -> bool {
+        unsafe { sqlite3session_isempty(self.session) != 0 }
+    }
+
+    /// Returns the changes recorded so far, with the old and new values of
+    /// every changed row.
+    pub fn changeset(&self) -> SqliteResult<Changeset> {
+        let mut n = 0;
+        let mut p = ptr::mut_null();
+        try!(check(unsafe { sqlite3session_changeset(self.session, &mut n, &mut p) }));
+        Ok(unsafe { take_buffer(n, p) })
+    }
+
+    /// Like `changeset`, but smaller: updates and deletes only record the
+    /// primary key and the new values. Patchsets cannot be inverted, and
+    /// applying them detects fewer conflicts.
+    pub fn patchset(&self) -> SqliteResult<Changeset> {
+        let mut n = 0;
+        let mut p = ptr::mut_null();
+        try!(check(unsafe { sqlite3session_patchset(self.session, &mut n, &mut p) }));
+        Ok(unsafe { take_buffer(n, p) })
+    }
+}
+
+impl Changeset {
+    /// Wraps a changeset or patchset received from elsewhere.
+    pub fn from_bytes(bytes: Vec<u8>) -> Changeset {
+        Changeset { bytes: bytes }
+    }
+
+    pub fn as_bytes<'a>(&'a self) -> &'a [u8] {
+        self.bytes.as_slice()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Returns the changeset that undoes this one. Fails with
+    /// `SQLITE_CORRUPT` for a patchset.
+    pub fn invert(&self) -> SqliteResult<Changeset> {
+        let mut n = 0;
+        let mut p = ptr::mut_null();
+        try!(check(unsafe {
+            sqlite3changeset_invert(self.bytes.len() as c_int, self.bytes.as_ptr() as *const c_void, &mut n, &mut p)
+        }));
+        Ok(unsafe { take_buffer(n, p) })
+    }
+
+    /// Returns a changeset with the effect of this one followed by `other`.
+    /// Both must be changesets or both patchsets.
+    pub fn concat(&self, other: &Changeset) -> SqliteResult<Changeset> {
+        let mut n = 0;
+        let mut p = ptr::mut_null();
+        try!(check(unsafe {
+            sqlite3changeset_concat(self.bytes.len() as c_int, self.bytes.as_ptr() as *mut c_void,
+                                    other.bytes.len() as c_int, other.bytes.as_ptr() as *mut c_void,
+                                    &mut n, &mut p)
+        }));
+        Ok(unsafe { take_buffer(n, p) })
+    }
+
+    /// Decodes the changed rows.
+    pub fn changes(&self) -> SqliteResult<Vec<Change>> {
+        let mut iter = ptr::mut_null();
+        try!(check(unsafe {
+            sqlite3changeset_start(&mut iter, self.bytes.len() as c_int, self.bytes.as_ptr() as *mut c_void)
+        }));
+        let mut changes = Vec::new();
+        let mut r;
+        loop {
+            r = unsafe { sqlite3changeset_next(iter) };
+            if r != SQLITE_ROW as c_int {
+                break;
+            }
+            match unsafe { change_from_iter(iter) } {
+                Ok(change) => changes.push(change),
+                Err(e) => {
+                    unsafe { sqlite3changeset_finalize(iter); }
+                    return Err(e);
+                }
+            }
+        }
+        let f = unsafe { sqlite3changeset_finalize(iter) };
+        if r != SQLITE_DONE as c_int {
+            try!(check(r));
+        }
+        try!(check(f));
+        Ok(changes)
+    }
+
+    #[allow(visible_private_types)]
+    pub fn apply(&self, dbh: &*mut dbh, on_conflict: ConflictHandler) -> SqliteResult<()> {
+        let mut ctx = ApplyContext { on_conflict: on_conflict, misused: false };
+        let arg = &mut ctx as *mut ApplyContext as *mut c_void;
+        let r = unsafe {
+            sqlite3changeset_apply(*dbh, self.bytes.len() as c_int, self.bytes.as_ptr() as *mut c_void,
+                                   None, Some(conflict_callback), arg)
+        };
+        if ctx.misused { Err(SQLITE_MISUSE) } else { check(r) }
+    }
+}
+
+/// What `conflict_callback` gets: the handler, and whether it answered
+/// `ConflictReplace` where SQLite does not allow it.
+struct ApplyContext<'a> {
+    on_conflict: ConflictHandler<'a>,
+    misused: bool,
+}
+
+/// Copies and frees a buffer allocated by SQLite.
+unsafe fn take_buffer(n: c_int, p: *mut c_void) -> Changeset {
+    if p.is_null() {
+        return Changeset { bytes: Vec::new() };
+    }
+    let bytes = slice::raw::buf_as_slice(p as *const u8, n as uint, |b| Vec::from_slice(b));
+    sqlite3_free(p);
+    Changeset { bytes: bytes }
+}
+
+unsafe fn values(iter: *mut changeset_iter,
+                 n: c_int,
+                 get: unsafe extern "C" fn(*mut changeset_iter, c_int, *mut *mut value) -> c_int)
+                 -> SqliteResult<Vec<Option<BindArg>>> {
+    let mut values = Vec::with_capacity(n as uint);
+    for i in range(0, n) {
+        let mut v = ptr::mut_null();
+        try!(check(get(iter, i, &mut v)));
+        values.push(if v.is_null() { None } else { Some(value_to_bind_arg(v)) });
+    }
+    Ok(values)
+}
+
+unsafe fn change_from_iter(iter: *mut changeset_iter) -> SqliteResult<Change> {
+    let mut table = ptr::null();
+    let mut n = 0;
+    let mut op = 0;
+    let mut indirect = 0;
+    try!(check(sqlite3changeset_op(iter, &mut table, &mut n, &mut op, &mut indirect)));
+    let mut pk = ptr::mut_null();
+    let mut n_pk = 0;
+    try!(check(sqlite3changeset_pk(iter, &mut pk, &mut n_pk)));
+    let operation: UpdateOperation = match FromPrimitive::from_i32(op as i32) {
+        Some(operation) => operation,
+        None => return Err(SQLITE_CORRUPT),
+    };
+    let old = if operation == SQLITE_INSERT { Vec::new() } else { try!(values(iter, n, sqlite3changeset_old)) };
+    let new = if operation == SQLITE_DELETE { Vec::new() } else { try!(values(iter, n, sqlite3changeset_new)) };
+    Ok(Change {
+        table: opt_string(table as *const c_char).unwrap_or(String::new()),
+        operation: operation,
+        indirect: indirect != 0,
+        primary_key: slice::raw::buf_as_slice(pk as *const u8, n_pk as uint, |pk| {
+            pk.iter().map(|&b| b != 0).collect()
+        }),
+        old: old,
+        new: new,
+    })
+}
+
+unsafe fn row_conflict(kind: ConflictKind, iter: *mut changeset_iter) -> SqliteResult<Conflict> {
+    let change = try!(change_from_iter(iter));
+    let conflicting = if kind == ConflictData || kind == ConflictConflict {
+        try!(values(iter, change.primary_key.len() as c_int, sqlite3changeset_conflict))
+    } else {
+        Vec::new()
+    };
+    Ok(RowConflict(ChangeConflict { kind: kind, change: change, conflicting: conflicting }))
+}
+
+extern "C" fn conflict_callback(arg: *mut c_void, kind: c_int, iter: *mut changeset_iter) -> c_int {
+    unsafe {
+        let ctx: &mut ApplyContext = mem::transmute(arg);
+        // the iterator holds no change for a foreign key conflict
+        let conflict = match kind {
+            1 => row_conflict(ConflictData, iter),
+            2 => row_conflict(ConflictNotFound, iter),
+            3 => row_conflict(ConflictConflict, iter),
+            4 => row_conflict(ConflictConstraint, iter),
+            5 => {
+                let mut n = 0;
+                check(sqlite3changeset_fk_conflicts(iter, &mut n)).map(|()| ForeignKeyConflict(n as int))
+            }
+            _ => Err(SQLITE_MISUSE),
+        };
+        let conflict = match conflict {
+            Ok(conflict) => conflict,
+            Err(_) => return ConflictAbort as c_int,
+        };
+        let action = catch_panic(ConflictAbort, || (ctx.on_conflict)(&conflict));
+        let replaceable = match conflict {
+            RowConflict(ref c) => c.kind == ConflictData || c.kind == ConflictConflict,
+            ForeignKeyConflict(_) => false,
+        };
+        if action == ConflictReplace && !replaceable {
+            ctx.misused = true;
+            return ConflictAbort as c_int;
+        }
+        action as c_int
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::Database;
+    use types::*;
+    use super::*;
+
+    fn open_pair() -> (Database, Database) {
+        let schema = "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT);
+                      INSERT INTO t VALUES (1, 'one');
+                      INSERT INTO t VALUES (2, 'two');";
+        let a = Database::new(":memory:").unwrap();
+        let b = Database::new(":memory:").unwrap();
+        a.exec(schema).unwrap();
+        b.exec(schema).unwrap();
+        (a, b)
+    }
+
+    fn rows(database: &Database) -> Vec<(int, String)> {
+        let cursor = database.prepare("SELECT id, v FROM t ORDER BY id").unwrap();
+        let mut rows = Vec::new();
+        while cursor.step() == Ok(SQLITE_ROW) {
+            rows.push((cursor.get_int(0), cursor.get_text(1)));
+        }
+        rows
+    }
+
+    fn text(s: &str) -> Option<BindArg> {
+        Some(Text(s.to_string()))
+    }
+
+    #[test]
+    fn record_inspect_apply_invert() {
+        let (a, b) = open_pair();
+        let changeset = {
+            let session = a.session("main").unwrap();
+            session.attach(None).unwrap();
+            assert!(session.is_empty());
+            a.exec("INSERT INTO t VALUES (3, 'three');
+                    UPDATE t SET v = 'uno' WHERE id = 1;
+                    DELETE FROM t WHERE id = 2;").unwrap();
+            assert!(!session.is_empty());
+            session.changeset().unwrap()
+        };
+
+        let changes = changeset.changes().unwrap();
+        assert_eq!(changes.len(), 3);
+        let insert = changes.iter().find(|c| c.operation == SQLITE_INSERT).unwrap();
+        assert_eq!(insert.table.as_slice(), "t");
+        assert_eq!(insert.primary_key, vec![true, false]);
+        assert!(insert.old.is_empty());
+        assert_eq!(insert.new, vec![Some(Integer64(3)), text("three")]);
+        let update = changes.iter().find(|c| c.operation == SQLITE_UPDATE).unwrap();
+        assert_eq!(update.old, vec![Some(Integer64(1)), text("one")]);
+        assert_eq!(update.new, vec![None, text("uno")]);
+        let delete = changes.iter().find(|c| c.operation == SQLITE_DELETE).unwrap();
+        assert_eq!(delete.old, vec![Some(Integer64(2)), text("two")]);
+        assert!(delete.new.is_empty());
+
+        assert_eq!(b.apply_changeset(&changeset, |_| ConflictAbort), Ok(()));
+        assert_eq!(rows(&b), rows(&a));
+
+        assert_eq!(a.apply_changeset(&changeset.invert().unwrap(), |_| ConflictAbort), Ok(()));
+        assert_eq!(rows(&a), vec![(1, "one".to_string()), (2, "two".to_string())]);
+    }
+
+    #[test]
+    fn conflicts() {
+        let (a, b) = open_pair();
+        let session = a.session("main").unwrap();
+        session.attach(Some("t")).unwrap();
+        a.exec("UPDATE t SET v = 'uno' WHERE id = 1; INSERT INTO t VALUES (3, 'three');").unwrap();
+        let changeset = session.changeset().unwrap();
+
+        b.exec("UPDATE t SET v = 'ein' WHERE id = 1; INSERT INTO t VALUES (3, 'drei');").unwrap();
+        let mut seen = Vec::new();
+        let r = b.apply_changeset(&changeset, |conflict| {
+            let conflict = match *conflict {
+                RowConflict(ref c) => c,
+                ForeignKeyConflict(_) => fail!("no foreign keys here"),
+            };
+            seen.push(conflict.kind.clone());
+            match conflict.kind {
+                ConflictData => {
+                    assert_eq!(conflict.conflicting, vec![Some(Integer64(1)), text("ein")]);
+                    ConflictReplace
+                }
+                _ => ConflictOmit
+            }
+        });
+        assert_eq!(r, Ok(()));
+        assert_eq!(seen.len(), 2);
+        assert!(seen.contains(&ConflictData));
+        assert!(seen.contains(&ConflictConflict));
+        assert_eq!(rows(&b), vec![(1, "uno".to_string()), (2, "two".to_string()), (3, "drei".to_string())]);
+
+        // Applying it again conflicts on row 1, whose value is now 'uno'.
+        assert_eq!(b.apply_changeset(&changeset, |_| ConflictAbort), Err(SQLITE_ABORT));
+        assert_eq!(rows(&b), vec![(1, "uno".to_string()), (2, "two".to_string()), (3, "drei".to_string())]);
+    }
+
+    #[test]
+    fn replace_only_where_allowed() {
+        let (a, b) = open_pair();
+        let session = a.session("main").unwrap();
+        session.attach(Some("t")).unwrap();
+        a.exec("INSERT INTO t VALUES (3, 'three'); DELETE FROM t WHERE id = 2;").unwrap();
+        let changeset = session.changeset().unwrap();
+
+        // deleting a row that is not there cannot be "replaced"
+        b.exec("DELETE FROM t WHERE id = 2").unwrap();
+        assert_eq!(b.apply_changeset(&changeset, |_| ConflictReplace), Err(SQLITE_MISUSE));
+        assert_eq!(rows(&b), vec![(1, "one".to_string())]);
+    }
+
+    #[test]
+    fn foreign_key_conflicts() {
+        let schema = "PRAGMA foreign_keys = ON;
+                      CREATE TABLE parent (id INTEGER PRIMARY KEY);
+                      CREATE TABLE child (id INTEGER PRIMARY KEY, parent INTEGER REFERENCES parent(id));
+                      INSERT INTO parent VALUES (1);";
+        let a = Database::new(":memory:").unwrap();
+        let b = Database::new(":memory:").unwrap();
+        a.exec(schema).unwrap();
+        b.exec(schema).unwrap();
+        b.exec("DELETE FROM parent").unwrap();
+
+        let session = a.session("main").unwrap();
+        session.attach(Some("child")).unwrap();
+        a.exec("INSERT INTO child VALUES (1, 1)").unwrap();
+        let changeset = session.changeset().unwrap();
+
+        let mut seen = Vec::new();
+        assert_eq!(b.apply_changeset(&changeset, |conflict| {
+            seen.push(match *conflict {
+                ForeignKeyConflict(n) => n,
+                RowConflict(ref c) => fail!("unexpected conflict {}", c),
+            });
+            ConflictOmit
+        }), Ok(()));
+        assert_eq!(seen, vec![1]);
+
+        b.exec("DELETE FROM child").unwrap();
+        assert_eq!(b.apply_changeset(&changeset, |_| ConflictReplace), Err(SQLITE_MISUSE));
+        let cursor = b.prepare("SELECT count(*) FROM child").unwrap();
+        assert_eq!(cursor.step(), Ok(SQLITE_ROW));
+        assert_eq!(cursor.get_int(0), 0);
+    }
+
+    #[test]
+    fn concat_and_patchset() {
+        let (a, b) = open_pair();
+        let first_session = a.session("main").unwrap();
+        first_session.attach(None).unwrap();
+        a.exec("INSERT INTO t VALUES (3, 'three')").unwrap();
+        let first = first_session.changeset().unwrap();
+
+        let second_session = a.session("main").unwrap();
+        second_session.attach(None).unwrap();
+        a.exec("UPDATE t SET v = 'tres' WHERE id = 3; UPDATE t SET v = 'uno' WHERE id = 1;").unwrap();
+        let second = second_session.changeset().unwrap();
+
+        let both = first.concat(&second).unwrap();
+        let changes = both.changes().unwrap();
+        assert_eq!(changes.iter().filter(|c| c.operation == SQLITE_INSERT).count(), 1);
+        let insert = changes.iter().find(|c| c.operation == SQLITE_INSERT).unwrap();
+        assert_eq!(insert.new, vec![Some(Integer64(3)), text("tres")]);
+
+        let patchset = first_session.patchset().unwrap();
+        assert!(patchset.as_bytes().len() < first_session.changeset().unwrap().as_bytes().len());
+        assert_eq!(patchset.invert(), Err(SQLITE_CORRUPT));
+        assert_eq!(b.apply_changeset(&patchset, |_| ConflictAbort), Ok(()));
+        assert_eq!(rows(&b), rows(&a));
+    }
+}
@@ -1,6 +1,6 @@
 #![crate_name = "sqlite3"]
 #![crate_type = "lib"]
-#![feature(globs, phase, unsafe_destructor)]
+#![feature(globs, phase, unsafe_destructor, unboxed_closures, overloaded_calls)]
 #[phase(plugin, link)] extern crate log;
 extern crate debug;
 
@@ -36,18 +36,49 @@ extern crate debug;
 */
 
 extern crate libc;
+extern crate time;
 
+pub use authorizer::*;
 pub use cursor::*;
 pub use database::*;
+pub use pragma::*;
 use ffi::*;
+use std::string;
 pub use types::*;
 
+#[cfg(feature = "async")]
+pub mod async;
+pub mod authorizer;
 pub mod cursor;
 pub mod database;
+pub mod extension;
+pub mod faultvfs;
+pub mod memvfs;
+pub mod migrations;
+pub mod pool;
+pub mod pragma;
 pub mod query;
+pub mod schema;
+#[cfg(feature = "session")]
+#[cfg(not(feature = "loadable_extension"))]
+pub mod session;
 mod ffi;
+mod util;
+#[cfg(feature = "loadable_extension")]
+mod api_routines;
 
 pub mod types;
+pub mod vfs;
+pub mod vtab;
+pub mod wal;
+pub mod worker;
+
+// Lets `sqlite3_extension_init!`, which names `::sqlite3::extension`, be
+// used in this crate's own tests.
+#[cfg(test)]
+mod sqlite3 {
+    pub use extension;
+}
 
 
 
@@ -70,6 +101,40 @@ pub fn sqlite_complete(sql: &str) -> SqliteResult<bool> {
     }
 }
 
+/// Returns the version of the SQLite library in use, e.g. "3.45.1".
+/// See http://www.sqlite.org/c3ref/libversion.html
+pub fn libversion() -> String {
+    unsafe { string::raw::from_buf(sqlite3_libversion() as *const u8) }
+}
+
+/// Returns the version as a number, e.g. 3045001 for "3.45.1".
+pub fn libversion_number() -> int {
+    unsafe { sqlite3_libversion_number() as int }
+}
+
+/// Returns the date, time and hash of the check-in SQLite was built from.
+pub fn sourceid() -> String {
+    unsafe { string::raw::from_buf(sqlite3_sourceid() as *const u8) }
+}
+
+/// Returns whether SQLite was compiled with option `name`, which may omit
+/// the "SQLITE_" prefix, e.g. "ENABLE_FTS5" or "THREADSAFE=1".
+/// See http://www.sqlite.org/c3ref/compileoption_get.html
+pub fn compileoption_used(name: &str) -> bool {
+    name.with_c_str(|_name| unsafe { sqlite3_compileoption_used(_name) }) != 0
+}
+
+/// Returns the threading mode SQLite was compiled with. Connections can
+/// only be opened if it is not `SingleThread`; see `Database` for why
+/// they may then move between threads.
+/// See http://www.sqlite.org/c3ref/threadsafe.html
+pub fn threading_mode() -> ThreadingMode {
+    match unsafe { sqlite3_threadsafe() } {
+        0 => SingleThread,
+        2 => MultiThread,
+        _ => Serialized,
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -77,7 +142,11 @@ mod tests {
     use database::*;
     use super::*;
     use types::*;
+    use std::io::TempDir;
+    use std::io::timer;
     use std::ptr;
+    use std::time::Duration;
+    use std::i64;
 
     fn checked_prepare<'db>(database: &'db Database, sql: &str) -> Cursor<'db> {
         match database.prepare(sql) {
@@ -240,6 +309,36 @@ mod tests {
         assert_eq!(sth.get_column_names(), vec!("id".to_string(), "v".to_string()));
     }
 
+    #[test]
+    fn column_count_before_step() {
+        let database = checked_open();
+        checked_exec(&database, "CREATE TABLE test (id INTEGER PRIMARY KEY, v VARCHAR(10))");
+        let sth = checked_prepare(&database, "SELECT id, v AS value, 1 + 1 FROM test");
+        assert_eq!(sth.get_column_count(), 3);
+        assert_eq!(sth.get_data_count(), 0);
+        assert_eq!(sth.get_column_decltype(0), Some("INTEGER".to_string()));
+        assert_eq!(sth.get_column_decltype(1), Some("VARCHAR(10)".to_string()));
+        assert_eq!(sth.get_column_decltype(2), None);
+    }
+
+    #[cfg(feature = "column_metadata")]
+    #[test]
+    fn column_origin() {
+        let database = checked_open();
+        checked_exec(&database, "CREATE TABLE test (id INTEGER PRIMARY KEY AUTOINCREMENT, v TEXT NOT NULL)");
+        let sth = checked_prepare(&database, "SELECT v AS value, 1 + 1 FROM test");
+        assert_eq!(sth.get_column_database_name(0), Some("main".to_string()));
+        assert_eq!(sth.get_column_table_name(0), Some("test".to_string()));
+        assert_eq!(sth.get_column_origin_name(0), Some("v".to_string()));
+        assert_eq!(sth.get_column_origin_name(1), None);
+
+        let meta = database.get_table_column_metadata(None, "test", "id").unwrap();
+        assert_eq!(meta.decl_type, Some("INTEGER".to_string()));
+        assert_eq!(meta.collation.as_slice(), "BINARY");
+        assert!(meta.primary_key && meta.autoincrement);
+        assert!(database.get_table_column_metadata(Some("main"), "test", "nosuch").is_err());
+    }
+
     #[test]
     #[should_fail]
     fn failed_prepare() {
@@ -312,6 +411,423 @@ mod tests {
         }
     }
 
+    #[test]
+    fn exec_with_callback_rows() {
+        let database = checked_open();
+        checked_exec(&database,
+            "CREATE TABLE test (id INTEGER, v TEXT);
+             INSERT INTO test (id, v) VALUES (1, 'a');
+             INSERT INTO test (id, v) VALUES (2, NULL);"
+        );
+        let mut rows = Vec::new();
+        let r = database.exec_with_callback("SELECT id, v FROM test ORDER BY id", |cols, vals| {
+            assert_eq!(cols, &["id".to_string(), "v".to_string()]);
+            rows.push(vals.to_vec());
+            true
+        });
+        assert!(r.is_ok());
+        assert_eq!(rows, vec!(vec!(Some("1".to_string()), Some("a".to_string())),
+                              vec!(Some("2".to_string()), None)));
+    }
+
+    #[test]
+    fn exec_with_callback_abort() {
+        let database = checked_open();
+        let mut calls = 0i;
+        let r = database.exec_with_callback("SELECT 1 UNION ALL SELECT 2", |_, _| {
+            calls += 1;
+            false
+        });
+        assert_eq!(calls, 1);
+        assert_eq!(r, Err(SQLITE_ABORT));
+    }
+
+    #[test]
+    fn exec_error_message() {
+        let database = checked_open();
+        let err = database.exec_with_message("SELECT * FROM missing").unwrap_err();
+        assert_eq!(err.kind, SQLITE_ERROR);
+        assert_eq!(err.desc.as_slice(), "no such table: missing");
+    }
+
+    #[test]
+    fn interrupt_from_other_thread() {
+        let database = checked_open();
+        let handle = database.interrupt_handle();
+        let (tx, rx) = channel();
+        spawn(proc() {
+            rx.recv();
+            loop {
+                handle.interrupt();
+                timer::sleep(Duration::milliseconds(5));
+                if rx.try_recv().is_ok() { break }
+            }
+        });
+        // bounded, so a missed interrupt fails the test instead of hanging it
+        let sth = checked_prepare(&database,
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c LIMIT 100000000)
+             SELECT count(*) FROM c");
+        tx.send(());
+        assert_eq!(sth.step(), Err(SQLITE_INTERRUPT));
+        tx.send(());
+    }
+
+    #[test]
+    fn interrupt_after_close() {
+        let handle = checked_open().interrupt_handle();
+        handle.interrupt();
+    }
+
+    #[test]
+    fn progress_handler_abort() {
+        let database = checked_open();
+        let mut calls = 0i;
+        database.set_progress_handler(10, move |&mut:| { calls += 1; calls < 5 });
+        let sth = checked_prepare(&database,
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c)
+             SELECT count(*) FROM c");
+        assert_eq!(sth.step(), Err(SQLITE_INTERRUPT));
+        database.clear_progress_handler();
+    }
+
+    #[test]
+    fn deadline_aborts_long_query() {
+        let database = checked_open();
+        let r = database.with_deadline(Duration::milliseconds(50), || {
+            let sth = checked_prepare(&database,
+                "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c)
+                 SELECT count(*) FROM c");
+            sth.step()
+        });
+        assert_eq!(r, Err(SQLITE_INTERRUPT));
+
+        let sth = checked_prepare(&database, "SELECT 1");
+        assert_eq!(sth.step(), Ok(SQLITE_ROW));
+    }
+
+    #[test]
+    fn deadline_saturates() {
+        let database = checked_open();
+        let r = database.with_deadline(Duration::milliseconds(-1), || {
+            let sth = checked_prepare(&database,
+                "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c)
+                 SELECT count(*) FROM c");
+            sth.step()
+        });
+        assert_eq!(r, Err(SQLITE_INTERRUPT));
+
+        let r = database.with_deadline(Duration::seconds(i64::MAX / 1000), || {
+            let sth = checked_prepare(&database, "SELECT 1");
+            sth.step()
+        });
+        assert_eq!(r, Ok(SQLITE_ROW));
+    }
+
+    #[test]
+    fn busy_handler_gives_up() {
+        let dir = TempDir::new("rustsqlite").unwrap();
+        let path = dir.path().join("busy.db");
+        let path = path.as_str().unwrap();
+        let db1 = Database::new(path).unwrap();
+        let db2 = Database::new(path).unwrap();
+        checked_exec(&db1, "CREATE TABLE test (id INTEGER); BEGIN EXCLUSIVE;");
+
+        let (tx, rx) = channel();
+        assert_eq!(db2.set_busy_handler(move |&mut: n: int| { tx.send(n); n < 2 }), Ok(()));
+        assert_eq!(db2.exec("INSERT INTO test VALUES (1)")), Err(SQLITE_BUSY));
+
+        // replacing the handler drops it, closing the channel
+        assert_eq!(db2.set_busy_timeout(Duration::milliseconds(10)), Ok(()));
+        assert_eq!(rx.iter().collect::<Vec<int>>(), vec!(0, 1, 2));
+        assert_eq!(db2.exec("INSERT INTO test VALUES (1)")), Err(SQLITE_BUSY));
+        assert_eq!(db2.clear_busy_handler(), Ok(()));
+
+        // a negative timeout does not wrap around into a huge one
+        assert_eq!(db2.set_busy_timeout(Duration::milliseconds(-1)), Ok(()));
+        assert_eq!(db2.exec("INSERT INTO test VALUES (1)")), Err(SQLITE_BUSY));
+    }
+
+    #[test]
+    fn update_and_transaction_hooks() {
+        let database = checked_open();
+        checked_exec(&database, "CREATE TABLE test (id INTEGER PRIMARY KEY, v TEXT)");
+
+        let (tx, rx) = channel();
+        database.set_update_hook(move |&mut: op, db: String, table: String, rowid| {
+            tx.send((op, db, table, rowid))
+        });
+        let (commit_tx, commit_rx) = channel();
+        database.set_commit_hook(move |&mut:| { commit_tx.send(()); true });
+        let (rollback_tx, rollback_rx) = channel();
+        database.set_rollback_hook(move |&mut:| rollback_tx.send(()));
+
+        checked_exec(&database,
+            "INSERT INTO test (id, v) VALUES (7, 'a');
+             UPDATE test SET v = 'b' WHERE id = 7;
+             DELETE FROM test WHERE id = 7;");
+        checked_exec(&database, "BEGIN; INSERT INTO test (v) VALUES ('c'); ROLLBACK;");
+        database.clear_update_hook();
+        database.clear_commit_hook();
+        database.clear_rollback_hook();
+
+        assert_eq!(rx.iter().collect::<Vec<(UpdateOperation, String, String, i64)>>(),
+                   vec!((SQLITE_INSERT, "main".to_string(), "test".to_string(), 7),
+                        (SQLITE_UPDATE, "main".to_string(), "test".to_string(), 7),
+                        (SQLITE_DELETE, "main".to_string(), "test".to_string(), 7),
+                        (SQLITE_INSERT, "main".to_string(), "test".to_string(), 1)));
+        assert_eq!(commit_rx.iter().count(), 3);
+        assert_eq!(rollback_rx.iter().count(), 1);
+    }
+
+    #[test]
+    fn commit_hook_veto() {
+        let database = checked_open();
+        checked_exec(&database, "CREATE TABLE test (id INTEGER)");
+        database.set_commit_hook(move |&mut:| false);
+        assert_eq!(database.exec("INSERT INTO test VALUES (1)"), Err(SQLITE_CONSTRAINT));
+        database.clear_commit_hook();
+
+        let sth = checked_prepare(&database, "SELECT count(*) FROM test");
+        assert_eq!(sth.step(), Ok(SQLITE_ROW));
+        assert_eq!(sth.get_int(0), 0);
+    }
+
+    #[cfg(feature = "preupdate_hook")]
+    #[cfg(not(feature = "loadable_extension"))]
+    #[test]
+    fn preupdate_hook_values() {
+        let database = checked_open();
+        checked_exec(&database, "CREATE TABLE test (id INTEGER PRIMARY KEY, v TEXT)");
+        let (tx, rx) = channel();
+        database.set_preupdate_hook(move |&mut: change: &PreUpdate| {
+            assert_eq!(change.column_count(), 2);
+            assert_eq!(change.depth(), 0);
+            let old = if change.operation() == SQLITE_INSERT { Null } else { change.old(1).unwrap() };
+            let new = if change.operation() == SQLITE_DELETE { Null } else { change.new(1).unwrap() };
+            tx.send((change.operation(), change.table_name().to_string(), old, new));
+        });
+        checked_exec(&database,
+            "INSERT INTO test (id, v) VALUES (1, 'a');
+             UPDATE test SET v = 'b' WHERE id = 1;
+             DELETE FROM test WHERE id = 1;");
+        database.clear_preupdate_hook();
+
+        let t = "test".to_string();
+        assert_eq!(rx.iter().collect::<Vec<(UpdateOperation, String, BindArg, BindArg)>>(),
+                   vec!((SQLITE_INSERT, t.clone(), Null, Text("a".to_string())),
+                        (SQLITE_UPDATE, t.clone(), Text("a".to_string()), Text("b".to_string())),
+                        (SQLITE_DELETE, t.clone(), Text("b".to_string()), Null)));
+    }
+
+    #[test]
+    fn trace_events() {
+        let database = checked_open();
+        let (tx, rx) = channel();
+        assert_eq!(database.trace(SQLITE_TRACE_STMT | SQLITE_TRACE_PROFILE | SQLITE_TRACE_ROW,
+                                  move |&mut: event: TraceEvent| tx.send(event)), Ok(()));
+        {
+            let sth = checked_prepare(&database, "SELECT ?");
+            assert_eq!(sth.bind_param(1, &Integer(42)), Ok(()));
+            assert_eq!(sth.step(), Ok(SQLITE_ROW));
+            assert_eq!(sth.step(), Ok(SQLITE_DONE));
+        }
+        assert_eq!(database.clear_trace(), Ok(()));
+
+        let events = rx.iter().collect::<Vec<TraceEvent>>();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], TraceStmt("SELECT 42".to_string()));
+        assert_eq!(events[1], TraceRow("SELECT ?".to_string()));
+        match events[2] {
+            TraceProfile(ref sql, ns) => {
+                assert_eq!(sql.as_slice(), "SELECT ?");
+                assert!(ns >= 0);
+            }
+            ref e => fail!("unexpected event {}", e)
+        }
+    }
+
+    #[test]
+    fn move_database_between_threads() {
+        assert!(threading_mode() != SingleThread);
+        let database = Database::open_with_flags(":memory:",
+                                                 SQLITE_OPEN_READWRITE | SQLITE_OPEN_NOMUTEX,
+                                                 None).unwrap();
+        checked_exec(&database, "CREATE TABLE test (id INTEGER)");
+
+        let (tx, rx) = channel();
+        spawn(proc() {
+            checked_exec(&database, "INSERT INTO test VALUES (1)");
+            tx.send(database);
+        });
+        let database = rx.recv();
+
+        let sth = checked_prepare(&database, "SELECT count(*) FROM test");
+        assert_eq!(sth.step(), Ok(SQLITE_ROW));
+        assert_eq!(sth.get_int(0), 1);
+    }
+
+    #[test]
+    fn open_with_flags() {
+        let dir = TempDir::new("rustsqlite").unwrap();
+        let path = dir.path().join("flags.db");
+        let path = path.as_str().unwrap();
+        assert_eq!(Database::open_with_flags(path, SQLITE_OPEN_READONLY, None).err(),
+                   Some(SQLITE_CANTOPEN));
+
+        let database = Database::open_with_flags(
+            path, SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE | SQLITE_OPEN_FULLMUTEX, None).unwrap();
+        checked_exec(&database, "CREATE TABLE test (id INTEGER)");
+        let readonly = Database::open_with_flags(path, SQLITE_OPEN_READONLY, None).unwrap();
+        assert_eq!(readonly.exec("INSERT INTO test VALUES (1)"), Err(SQLITE_READONLY));
+        assert_eq!(Database::open_with_flags(path, SQLITE_OPEN_READWRITE, Some("nosuchvfs")).err(),
+                   Some(SQLITE_ERROR));
+    }
+
+    #[test]
+    fn backup_to_other_connection() {
+        let source = checked_open();
+        checked_exec(&source, "CREATE TABLE test (id INTEGER); INSERT INTO test VALUES (5);");
+        let dest = checked_open();
+        assert_eq!(source.backup_to("main", &dest), Ok(()));
+        let sth = checked_prepare(&dest, "SELECT id FROM test");
+        assert_eq!(sth.step(), Ok(SQLITE_ROW));
+        assert_eq!(sth.get_int(0), 5);
+        assert_eq!(source.backup_to("nosuch", &dest), Err(SQLITE_ERROR));
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let source = checked_open();
+        checked_exec(&source, "CREATE TABLE test (id INTEGER); INSERT INTO test VALUES (5);");
+        let bytes = source.serialize("main").unwrap();
+        assert_eq!(bytes.slice_to(16), b"SQLite format 3\0".as_slice());
+        assert_eq!(source.serialize("nosuch"), Err(SQLITE_ERROR));
+
+        let mut copy = Database::from_bytes(bytes.as_slice(), false).unwrap();
+        checked_exec(&copy, "INSERT INTO test SELECT id + 1 FROM test; INSERT INTO test SELECT id + 2 FROM test;");
+        {
+            let sth = checked_prepare(&copy, "SELECT sum(id) FROM test");
+            assert_eq!(sth.step(), Ok(SQLITE_ROW));
+            assert_eq!(sth.get_int(0), 26);
+        }
+        let serialized = copy.serialize("main").unwrap();
+        assert_eq!(copy.serialize_no_copy("main"), Some(serialized.as_slice()));
+
+        let read_only = Database::from_bytes(bytes.as_slice(), true).unwrap();
+        assert_eq!(read_only.exec("INSERT INTO test VALUES (6)"), Err(SQLITE_READONLY));
+        let sth = checked_prepare(&read_only, "SELECT id FROM test");
+        assert_eq!(sth.step(), Ok(SQLITE_ROW));
+        assert_eq!(sth.get_int(0), 5);
+
+        assert!(Database::from_bytes(b"not a database".as_slice(), true)
+                .and_then(|db| db.exec("SELECT * FROM sqlite_master")).is_err());
+    }
+
+    #[test]
+    fn load_extension_reports_errors() {
+        let database = checked_open();
+        match database.load_extension("/nonexistent/libnosuch", None) {
+            Err(e) => {
+                assert_eq!(e.kind, SQLITE_ERROR);
+                assert!(e.desc.as_slice().contains("nosuch"));
+            }
+            Ok(()) => fail!("loaded a missing extension"),
+        }
+
+        let _enabled = database.enable_load_extension().unwrap();
+        let r = database.exec_with_message("SELECT load_extension('/nonexistent/libnosuch')");
+        assert!(r.unwrap_err().desc.as_slice().contains("not authorized"));
+    }
+
+    #[test]
+    fn library_version() {
+        let version = libversion();
+        assert!(version.as_slice().starts_with("3."));
+        let parts: Vec<int> = version.as_slice().split('.').map(|p| from_str(p).unwrap()).collect();
+        let number = parts[0] * 1000000 + parts[1] * 1000 + if parts.len() > 2 { parts[2] } else { 0 };
+        assert_eq!(libversion_number(), number);
+        assert!(!sourceid().is_empty());
+        assert!(!compileoption_used("NO_SUCH_OPTION"));
+        assert!(compileoption_used("SQLITE_THREADSAFE=1") == (threading_mode() == Serialized));
+    }
+
+    #[test]
+    #[cfg(all(feature = "bundled", feature = "fts5"))]
+    fn bundled_fts5() {
+        assert!(compileoption_used("ENABLE_FTS5"));
+        let database = checked_open();
+        checked_exec(&database, "CREATE VIRTUAL TABLE docs USING fts5(body);
+                                 INSERT INTO docs VALUES ('hello world');");
+        let sth = checked_prepare(&database, "SELECT count(*) FROM docs WHERE docs MATCH 'hello'");
+        assert_eq!(sth.step(), Ok(SQLITE_ROW));
+        assert_eq!(sth.get_int(0), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "bundled")]
+    fn bundled_version_and_json() {
+        let expected = include_str!("../sqlite3/VERSION");
+        assert_eq!(libversion().as_slice(), expected.lines().next().unwrap());
+        assert!(!compileoption_used("OMIT_JSON"));
+        let database = checked_open();
+        let sth = checked_prepare(&database, "SELECT json_extract('{\"a\": 7}', '$.a')");
+        assert_eq!(sth.step(), Ok(SQLITE_ROW));
+        assert_eq!(sth.get_int(0), 7);
+    }
+
+    #[test]
+    fn create_function() {
+        let database = checked_open();
+        let mut calls = 0u;
+        database.create_function("half", 1, true, move |&mut: args: &[BindArg]| {
+            calls += 1;
+            match args[0] {
+                Integer64(v) => Ok(Float64(v as f64 / 2.0)),
+                Null => Ok(Null),
+                _ => Err(SqliteError { kind: SQLITE_RANGE, desc: format!("call {}: not an integer", calls) })
+            }
+        }).unwrap();
+
+        let sth = checked_prepare(&database, "SELECT half(5), half(NULL)");
+        assert_eq!(sth.step(), Ok(SQLITE_ROW));
+        assert_eq!(sth.get_f64(0), 2.5);
+        assert!(match sth.get_column_type(1) { SQLITE_NULL => true, _ => false });
+        drop(sth);
+
+        let sth = checked_prepare(&database, "SELECT half('x')");
+        assert_eq!(sth.step(), Err(SQLITE_RANGE));
+        assert_eq!(database.get_errmsg().as_slice(), "call 3: not an integer");
+        drop(sth);
+
+        // Wrong number of arguments.
+        assert!(database.prepare("SELECT half(1, 2)").is_err());
+    }
+
+    #[test]
+    fn create_function_panics() {
+        let database = checked_open();
+        database.create_function("boom", 0, false, move |&mut: _: &[BindArg]| -> Result<BindArg, SqliteError> {
+            fail!("boom")
+        }).unwrap();
+        let sth = checked_prepare(&database, "SELECT boom()");
+        assert_eq!(sth.step(), Err(SQLITE_ERROR));
+    }
+
+    #[test]
+    fn create_collation() {
+        let database = checked_open();
+        database.create_collation("reverse", move |&mut: a: &str, b: &str| b.cmp(a)).unwrap();
+        checked_exec(&database, "CREATE TABLE t (v TEXT);
+                                 INSERT INTO t VALUES ('a'), ('c'), ('b');");
+        let sth = checked_prepare(&database, "SELECT v FROM t ORDER BY v COLLATE reverse");
+        let mut seen = Vec::new();
+        while sth.step() == Ok(SQLITE_ROW) {
+            seen.push(sth.get_text(0));
+        }
+        assert_eq!(seen, vec!["c".to_string(), "b".to_string(), "a".to_string()]);
+        assert!(database.prepare("SELECT v FROM t ORDER BY v COLLATE nosuch").is_err());
+    }
+
     #[test]
     fn check_complete_sql() {
         let r1 = sqlite_complete("SELECT * FROM");
@@ -41,7 +41,7 @@ pub enum ResultOk {
     _unused // avoid: unsupported representation for univariant enum [E0083]
 }
 
-#[deriving(PartialEq, Eq, Show, FromPrimitive)]
+#[deriving(PartialEq, Eq, Show, Clone, FromPrimitive)]
 #[repr(C)]
 pub enum ResultError {
     SQLITE_ERROR      =  1,
@@ -87,6 +87,94 @@ pub enum ResultStep {
     SQLITE_DONE       = 101,
 }
 
+/// The kind of change reported by an update hook.
+#[deriving(PartialEq, Eq, Show, FromPrimitive)]
+#[repr(C)]
+pub enum UpdateOperation {
+    SQLITE_DELETE     =  9,
+    SQLITE_INSERT     = 18,
+    SQLITE_UPDATE     = 23,
+}
+
+/// How hard a WAL checkpoint tries, see `Database::wal_checkpoint`.
+/// See http://www.sqlite.org/c3ref/wal_checkpoint_v2.html
+#[deriving(PartialEq, Eq, Show, Clone)]
+#[repr(C)]
+pub enum CheckpointMode {
+    /// Checkpoint as many frames as possible without waiting on readers or
+    /// writers.
+    SQLITE_CHECKPOINT_PASSIVE  = 0,
+    /// Wait for writers, then checkpoint everything.
+    SQLITE_CHECKPOINT_FULL     = 1,
+    /// Like FULL, then wait for readers so the log restarts from the
+    /// beginning.
+    SQLITE_CHECKPOINT_RESTART  = 2,
+    /// Like RESTART, then truncate the log file to zero bytes.
+    SQLITE_CHECKPOINT_TRUNCATE = 3,
+}
+
+bitflags! {
+    #[doc = "Flags for `Database::open_with_flags`, also passed to `Vfs::open`."]
+    flags OpenFlags: i32 {
+        static SQLITE_OPEN_READONLY      = 0x00000001,
+        static SQLITE_OPEN_READWRITE     = 0x00000002,
+        static SQLITE_OPEN_CREATE        = 0x00000004,
+        static SQLITE_OPEN_DELETEONCLOSE = 0x00000008,
+        static SQLITE_OPEN_EXCLUSIVE     = 0x00000010,
+        static SQLITE_OPEN_URI           = 0x00000040,
+        static SQLITE_OPEN_MEMORY        = 0x00000080,
+        static SQLITE_OPEN_MAIN_DB       = 0x00000100,
+        static SQLITE_OPEN_TEMP_DB       = 0x00000200,
+        static SQLITE_OPEN_TRANSIENT_DB  = 0x00000400,
+        static SQLITE_OPEN_MAIN_JOURNAL  = 0x00000800,
+        static SQLITE_OPEN_TEMP_JOURNAL  = 0x00001000,
+        static SQLITE_OPEN_SUBJOURNAL    = 0x00002000,
+        static SQLITE_OPEN_SUPER_JOURNAL = 0x00004000,
+        static SQLITE_OPEN_NOMUTEX       = 0x00008000,
+        static SQLITE_OPEN_FULLMUTEX     = 0x00010000,
+        static SQLITE_OPEN_SHAREDCACHE   = 0x00020000,
+        static SQLITE_OPEN_PRIVATECACHE  = 0x00040000,
+        static SQLITE_OPEN_WAL           = 0x00080000
+    }
+}
+
+/// How the SQLite library was compiled to deal with threads.
+/// See http://www.sqlite.org/threadsafe.html
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum ThreadingMode {
+    /// No mutexes at all; SQLite must only ever be used from one thread.
+    SingleThread,
+    /// A connection may be used by one thread at a time.
+    MultiThread,
+    /// A connection may be used by several threads at once.
+    Serialized,
+}
+
+bitflags! {
+    #[doc = "The kinds of event a trace callback subscribes to."]
+    flags TraceMask: u32 {
+        static SQLITE_TRACE_STMT    = 0x01,
+        static SQLITE_TRACE_PROFILE = 0x02,
+        static SQLITE_TRACE_ROW     = 0x04,
+        static SQLITE_TRACE_CLOSE   = 0x08
+    }
+}
+
+/// An event delivered to a trace callback.
+#[deriving(PartialEq, Show, Clone)]
+pub enum TraceEvent {
+    /// A statement started running; carries its SQL with bound parameters
+    /// expanded.
+    TraceStmt(String),
+    /// A statement finished; carries its SQL and how long it ran, in
+    /// nanoseconds.
+    TraceProfile(String, i64),
+    /// A statement produced a row; carries its SQL.
+    TraceRow(String),
+    /// The connection is closing.
+    TraceClose,
+}
+
 #[deriving(Show, PartialEq)]
 pub enum BindArg {
     Text(String),
@@ -106,9 +194,29 @@ pub enum ColumnType {
     SQLITE_NULL,
 }
 
+/// The declaration of a table column, see
+/// `Database::get_table_column_metadata`.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub struct ColumnMetadata {
+    /// The declared type, if any.
+    pub decl_type: Option<String>,
+    /// The name of the default collation sequence.
+    pub collation: String,
+    pub not_null: bool,
+    pub primary_key: bool,
+    pub autoincrement: bool,
+}
+
 #[must_use]
 pub type SqliteResult<T> = Result<T, ResultError>;
 
+/// An error code together with the message SQLite reported for it.
+#[deriving(PartialEq, Eq, Show)]
+pub struct SqliteError {
+    pub kind: ResultError,
+    pub desc: String,
+}
+
 // TODO: c_int?
 pub fn check(r: i32) -> SqliteResult<()> {
     if r == SQLITE_OK as i32 { Ok(()) }
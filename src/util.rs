@@ -0,0 +1,126 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+use ffi::*;
+use libc::{c_char, c_int, c_void};
+use std::ptr;
+use std::rt::unwind;
+use std::slice;
+use std::string;
+use std::time::Duration;
+use std::u64;
+use time;
+use types::*;
+
+/// Runs `f`, returning `default` instead of unwinding into SQLite if it
+/// fails. Used by every callback that SQLite invokes on our behalf.
+pub fn catch_panic<T>(default: T, f: || -> T) -> T {
+    let mut out = None;
+    let r = unsafe { unwind::try(|| out = Some(f())) };
+    match (r, out) {
+        (Ok(()), Some(v)) => v,
+        _ => default
+    }
+}
+
+/// Returns the `precise_time_ns` instant `timeout` from now. Negative
+/// timeouts mean "now" and ones too long to represent saturate.
+pub fn deadline_after(timeout: Duration) -> u64 {
+    let now = time::precise_time_ns();
+    match timeout.num_nanoseconds() {
+        Some(ns) if ns <= 0 => now,
+        Some(ns) => now.checked_add(&(ns as u64)).unwrap_or(u64::MAX),
+        None if timeout.num_milliseconds() < 0 => now,
+        None => u64::MAX
+    }
+}
+
+/// Copies and frees a possibly-null error message that SQLite allocated,
+/// as returned through the `errmsg` argument of `sqlite3_exec` and
+/// `sqlite3_load_extension`.
+pub unsafe fn take_errmsg(errmsg: *mut c_char) -> Option<String> {
+    let desc = opt_string(errmsg as *const c_char);
+    sqlite3_free(errmsg as *mut c_void);
+    desc
+}
+
+/// Copies a possibly-null C string.
+pub unsafe fn opt_string(p: *const c_char) -> Option<String> {
+    if p.is_null() {
+        None
+    } else {
+        Some(string::raw::from_buf(p as *const u8))
+    }
+}
+
+/// Copies `msg` into memory SQLite can free, for error messages it takes
+/// ownership of.
+pub unsafe fn copy_message(msg: &str) -> *mut c_char {
+    let p = sqlite3_malloc64(msg.len() as u64 + 1) as *mut u8;
+    if !p.is_null() {
+        ptr::copy_nonoverlapping_memory(p, msg.as_ptr(), msg.len());
+        *p.offset(msg.len() as int) = 0;
+    }
+    p as *mut c_char
+}
+
+/// Copies a protected or unprotected `sqlite3_value`.
+pub unsafe fn value_to_bind_arg(v: *mut value) -> BindArg {
+    match sqlite3_value_type(v) {
+        1 /* SQLITE_INTEGER */ => Integer64(sqlite3_value_int64(v)),
+        2 /* SQLITE_FLOAT   */ => Float64(sqlite3_value_double(v)),
+        3 /* SQLITE_TEXT    */ => {
+            let txt = sqlite3_value_text(v);
+            Text(string::raw::from_buf_len(txt as *const u8, sqlite3_value_bytes(v) as uint))
+        }
+        4 /* SQLITE_BLOB    */ => {
+            let len = sqlite3_value_bytes(v) as uint;
+            Blob(slice::raw::buf_as_slice(sqlite3_value_blob(v), len, |bytes| Vec::from_slice(bytes)))
+        }
+        _ => Null
+    }
+}
+
+/// Makes `arg` the result of the SQL function or virtual table column
+/// being evaluated in `ctx`. SQLite copies text and blobs.
+pub unsafe fn set_result(ctx: *mut context, arg: &BindArg) {
+    // SQLITE_TRANSIENT
+    let transient = -1 as *mut c_void;
+    match *arg {
+        Text(ref v) => sqlite3_result_text(ctx, v.as_ptr() as *const c_char, v.len() as c_int, transient),
+        StaticText(v) => sqlite3_result_text(ctx, v.as_ptr() as *const c_char, v.len() as c_int, transient),
+        Float64(v) => sqlite3_result_double(ctx, v),
+        Integer(v) => sqlite3_result_int64(ctx, v as i64),
+        Integer64(v) => sqlite3_result_int64(ctx, v),
+        Blob(ref v) => sqlite3_result_blob(ctx, v.as_ptr() as *const c_void, v.len() as c_int, transient),
+        Null => sqlite3_result_null(ctx),
+    }
+}
@@ -0,0 +1,661 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Storage backends written in Rust.
+//!
+//! Implement `Vfs` and `VfsFile`, register the result under a name with
+//! `register_vfs`, and pass that name to `Database::open_with_flags`.
+//! Shared libraries, randomness, sleeping and the clock are delegated to
+//! the default VFS.
+//! See http://www.sqlite.org/vfs.html
+
+use libc::{c_char, c_int, c_void};
+use std::mem;
+use std::ptr;
+use std::slice;
+
+use ffi::*;
+use types::*;
+use util::*;
+
+pub type VfsResult<T> = Result<T, ResultError>;
+
+static SQLITE_IOERR_SHORT_READ: c_int = 522;
+static SQLITE_SHM_LOCK: c_int = 2;
+static SQLITE_SHM_EXCLUSIVE: c_int = 8;
+static SQLITE_SYNC_DATAONLY: c_int = 0x10;
+
+/// A file lock level. Levels only ever go up one step at a time in
+/// `lock` and come down to `LockShared` or `LockNone` in `unlock`.
+/// See http://www.sqlite.org/c3ref/c_lock_exclusive.html
+#[deriving(PartialEq, Eq, PartialOrd, Ord, Show, Clone, FromPrimitive)]
+pub enum LockLevel {
+    LockNone      = 0,
+    LockShared    = 1,
+    LockReserved  = 2,
+    LockPending   = 3,
+    LockExclusive = 4,
+}
+
+/// What `Vfs::access` is asked about a file.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum AccessCheck {
+    AccessExists,
+    AccessReadWrite,
+    AccessRead,
+}
+
+/// An open file. Offsets and sizes are in bytes.
+/// See http://www.sqlite.org/c3ref/io_methods.html
+pub trait VfsFile {
+    /// Reads into `buf` from `offset` and returns how many bytes were
+    /// read. Reading fewer than `buf.len()` bytes means end of file; the
+    /// rest of `buf` is zeroed for SQLite.
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> VfsResult<uint>;
+    fn write(&mut self, buf: &[u8], offset: u64) -> VfsResult<()>;
+    fn truncate(&mut self, size: u64) -> VfsResult<()>;
+    /// Makes previous writes durable. `data_only` means the file's
+    /// metadata need not be synced.
+    fn sync(&mut self, data_only: bool) -> VfsResult<()>;
+    fn file_size(&mut self) -> VfsResult<u64>;
+    /// Raises the lock to `level`, failing with `SQLITE_BUSY` if another
+    /// connection holds a conflicting lock.
+    fn lock(&mut self, level: LockLevel) -> VfsResult<()>;
+    /// Lowers the lock to `level`.
+    fn unlock(&mut self, level: LockLevel) -> VfsResult<()>;
+    /// Returns whether any connection holds a reserved or higher lock.
+    fn check_reserved_lock(&mut self) -> VfsResult<bool>;
+
+    fn sector_size(&self) -> uint { 4096 }
+
+    /// The `SQLITE_IOCAP_*` bits describing the file.
+    fn device_characteristics(&self) -> int { 0 }
+
+    /// Whether the `shm_*` methods are implemented, which WAL mode needs
+    /// unless the database uses exclusive locking mode.
+    fn supports_shm(&self) -> bool { false }
+
+    /// Returns a pointer to shared-memory region `region` of `size`
+    /// bytes, which must stay valid until `shm_unmap`. If the region does
+    /// not exist, creates it when `extend` is set and returns null
+    /// otherwise.
+    fn shm_map(&mut self, _region: uint, _size: uint, _extend: bool) -> VfsResult<*mut u8> {
+        Err(SQLITE_IOERR)
+    }
+
+    /// Takes (`lock`) or releases shared-memory locks `offset` to
+    /// `offset + n - 1`, shared or `exclusive`.
+    fn shm_lock(&mut self, _offset: uint, _n: uint, _lock: bool, _exclusive: bool) -> VfsResult<()> {
+        Err(SQLITE_IOERR)
+    }
+
+    fn shm_barrier(&mut self) {}
+
+    /// Unmaps the shared memory, deleting it if `delete` is set.
+    fn shm_unmap(&mut self, _delete: bool) -> VfsResult<()> { Ok(()) }
+}
+
+/// A storage backend.
+/// See http://www.sqlite.org/c3ref/vfs.html
+pub trait Vfs {
+    /// Opens `name`, or a new temporary file if `None`. `flags` tells what
+    /// kind of file it is (`SQLITE_OPEN_MAIN_DB`, `SQLITE_OPEN_WAL`, ...)
+    /// and whether to create or delete it on close.
+    fn open(&self, name: Option<&str>, flags: OpenFlags) -> VfsResult<Box<VfsFile + Send>>;
+    fn delete(&self, name: &str, sync_dir: bool) -> VfsResult<()>;
+    fn access(&self, name: &str, check: AccessCheck) -> VfsResult<bool>;
+
+    /// Returns the canonical name of `name`, which is what `open`,
+    /// `delete` and `access` receive.
+    fn full_pathname(&self, name: &str) -> VfsResult<String> { Ok(name.to_string()) }
+}
+
+struct VfsData {
+    vfs: Box<Vfs + Send + Sync>,
+    default: *mut sqlite3_vfs,
+}
+
+#[repr(C)]
+struct RawFile {
+    base: sqlite3_file,
+    file: *mut Box<VfsFile + Send>,
+}
+
+/// Registers `vfs` under `name`, making it the default for connections
+/// opened without a VFS name if `make_default` is set. Registered VFSes
+/// live until the process exits.
+/// See http://www.sqlite.org/c3ref/vfs_find.html
+pub fn register_vfs(name: &str, vfs: Box<Vfs + Send + Sync>, make_default: bool) -> SqliteResult<()> {
+    unsafe {
+        let default = sqlite3_vfs_find(ptr::null());
+        if default.is_null() {
+            return Err(SQLITE_ERROR);
+        }
+        let data = box VfsData { vfs: vfs, default: default };
+        let raw = box sqlite3_vfs {
+            version: 2,
+            os_file_size: mem::size_of::<RawFile>() as c_int,
+            max_pathname: (*default).max_pathname,
+            next: ptr::mut_null(),
+            name: name.to_c_str().unwrap(),
+            app_data: mem::transmute(data),
+            open: Some(vfs_open),
+            delete: Some(vfs_delete),
+            access: Some(vfs_access),
+            full_pathname: Some(vfs_full_pathname),
+            dl_open: Some(vfs_dl_open),
+            dl_error: Some(vfs_dl_error),
+            dl_sym: Some(vfs_dl_sym),
+            dl_close: Some(vfs_dl_close),
+            randomness: Some(vfs_randomness),
+            sleep: Some(vfs_sleep),
+            current_time: Some(vfs_current_time),
+            get_last_error: Some(vfs_get_last_error),
+            current_time_int64: Some(vfs_current_time_int64),
+        };
+        check(sqlite3_vfs_register(mem::transmute(raw), make_default as c_int))
+    }
+}
+
+unsafe fn vfs_data<'a>(vfs: *mut sqlite3_vfs) -> &'a VfsData {
+    &*((*vfs).app_data as *const VfsData)
+}
+
+unsafe fn file<'a>(f: *mut sqlite3_file) -> &'a mut Box<VfsFile + Send> {
+    &mut *(*(f as *mut RawFile)).file
+}
+
+fn result_code(r: VfsResult<()>) -> c_int {
+    match r {
+        Ok(()) => SQLITE_OK as c_int,
+        Err(e) => e as c_int
+    }
+}
+
+extern "C" fn vfs_open(vfs: *mut sqlite3_vfs,
+                       name: *const c_char,
+                       f: *mut sqlite3_file,
+                       flags: c_int,
+                       out_flags: *mut c_int) -> c_int {
+    unsafe {
+        let raw = f as *mut RawFile;
+        (*raw).base.methods = ptr::null();
+        (*raw).file = ptr::mut_null();
+        let name = opt_string(name);
+        let open_flags = OpenFlags::from_bits_truncate(flags as i32);
+        let data = vfs_data(vfs);
+        let r = catch_panic(Err(SQLITE_IOERR), || data.vfs.open(name.as_ref().map(|n| n.as_slice()), open_flags));
+        match r {
+            Ok(opened) => {
+                let methods = if opened.supports_shm() { &IO_METHODS_V2 } else { &IO_METHODS_V1 };
+                (*raw).file = mem::transmute(box opened);
+                (*raw).base.methods = methods as *const sqlite3_io_methods;
+                if !out_flags.is_null() {
+                    *out_flags = flags;
+                }
+                SQLITE_OK as c_int
+            }
+            Err(e) => e as c_int
+        }
+    }
+}
+
+extern "C" fn vfs_delete(vfs: *mut sqlite3_vfs, name: *const c_char, sync_dir: c_int) -> c_int {
+    unsafe {
+        let data = vfs_data(vfs);
+        let name = opt_string(name).unwrap_or(String::new());
+        result_code(catch_panic(Err(SQLITE_IOERR), || data.vfs.delete(name.as_slice(), sync_dir != 0)))
+    }
+}
+
+extern "C" fn vfs_access(vfs: *mut sqlite3_vfs, name: *const c_char, flags: c_int, out: *mut c_int) -> c_int {
+    unsafe {
+        let data = vfs_data(vfs);
+        let name = opt_string(name).unwrap_or(String::new());
+        let check = match flags {
+            1 => AccessReadWrite,
+            2 => AccessRead,
+            _ => AccessExists,
+        };
+        let r = catch_panic(Err(SQLITE_IOERR), || data.vfs.access(name.as_slice(), check.clone()));
+        match r {
+            Ok(b) => { *out = b as c_int; SQLITE_OK as c_int }
+            Err(e) => e as c_int
+        }
+    }
+}
+
+extern "C" fn vfs_full_pathname(vfs: *mut sqlite3_vfs, name: *const c_char, n_out: c_int, out: *mut c_char) -> c_int {
+    unsafe {
+        let data = vfs_data(vfs);
+        let name = opt_string(name).unwrap_or(String::new());
+        let r = catch_panic(Err(SQLITE_IOERR), || data.vfs.full_pathname(name.as_slice()));
+        match r {
+            Ok(full) => {
+                let bytes = full.as_bytes();
+                if bytes.len() + 1 > n_out as uint {
+                    return SQLITE_CANTOPEN as c_int;
+                }
+                ptr::copy_nonoverlapping_memory(out as *mut u8, bytes.as_ptr(), bytes.len());
+                *out.offset(bytes.len() as int) = 0;
+                SQLITE_OK as c_int
+            }
+            Err(e) => e as c_int
+        }
+    }
+}
+
+extern "C" fn vfs_dl_open(vfs: *mut sqlite3_vfs, name: *const c_char) -> *mut c_void {
+    unsafe {
+        let default = vfs_data(vfs).default;
+        ((*default).dl_open.unwrap())(default, name)
+    }
+}
+
+extern "C" fn vfs_dl_error(vfs: *mut sqlite3_vfs, n: c_int, msg: *mut c_char) {
+    unsafe {
+        let default = vfs_data(vfs).default;
+        ((*default).dl_error.unwrap())(default, n, msg)
+    }
+}
+
+extern "C" fn vfs_dl_sym(vfs: *mut sqlite3_vfs, handle: *mut c_void, sym: *const c_char) -> *mut c_void {
+    unsafe {
+        let default = vfs_data(vfs).default;
+        ((*default).dl_sym.unwrap())(default, handle, sym)
+    }
+}
+
+extern "C" fn vfs_dl_close(vfs: *mut sqlite3_vfs, handle: *mut c_void) {
+    unsafe {
+        let default = vfs_data(vfs).default;
+        ((*default).dl_close.unwrap())(default, handle)
+    }
+}
+
+extern "C" fn vfs_randomness(vfs: *mut sqlite3_vfs, n: c_int, out: *mut c_char) -> c_int {
+    unsafe {
+        let default = vfs_data(vfs).default;
+        ((*default).randomness.unwrap())(default, n, out)
+    }
+}
+
+extern "C" fn vfs_sleep(vfs: *mut sqlite3_vfs, micros: c_int) -> c_int {
+    unsafe {
+        let default = vfs_data(vfs).default;
+        ((*default).sleep.unwrap())(default, micros)
+    }
+}
+
+extern "C" fn vfs_current_time(vfs: *mut sqlite3_vfs, out: *mut f64) -> c_int {
+    unsafe {
+        let default = vfs_data(vfs).default;
+        ((*default).current_time.unwrap())(default, out)
+    }
+}
+
+extern "C" fn vfs_get_last_error(vfs: *mut sqlite3_vfs, n: c_int, out: *mut c_char) -> c_int {
+    unsafe {
+        let default = vfs_data(vfs).default;
+        match (*default).get_last_error {
+            Some(f) => f(default, n, out),
+            None => 0
+        }
+    }
+}
+
+extern "C" fn vfs_current_time_int64(vfs: *mut sqlite3_vfs, out: *mut i64) -> c_int {
+    unsafe {
+        let default = vfs_data(vfs).default;
+        match (*default).current_time_int64 {
+            Some(f) => f(default, out),
+            None => {
+                let mut day = 0f64;
+                let r = ((*default).current_time.unwrap())(default, &mut day);
+                *out = (day * 86400000.0) as i64;
+                r
+            }
+        }
+    }
+}
+
+static IO_METHODS_V1: sqlite3_io_methods = sqlite3_io_methods {
+    version: 1,
+    close: Some(file_close),
+    read: Some(file_read),
+    write: Some(file_write),
+    truncate: Some(file_truncate),
+    sync: Some(file_sync),
+    file_size: Some(file_size),
+    lock: Some(file_lock),
+    unlock: Some(file_unlock),
+    check_reserved_lock: Some(file_check_reserved_lock),
+    file_control: Some(file_control),
+    sector_size: Some(file_sector_size),
+    device_characteristics: Some(file_device_characteristics),
+    shm_map: None,
+    shm_lock: None,
+    shm_barrier: None,
+    shm_unmap: None,
+};
+
+static IO_METHODS_V2: sqlite3_io_methods = sqlite3_io_methods {
+    version: 2,
+    close: Some(file_close),
+    read: Some(file_read),
+    write: Some(file_write),
+    truncate: Some(file_truncate),
+    sync: Some(file_sync),
+    file_size: Some(file_size),
+    lock: Some(file_lock),
+    unlock: Some(file_unlock),
+    check_reserved_lock: Some(file_check_reserved_lock),
+    file_control: Some(file_control),
+    sector_size: Some(file_sector_size),
+    device_characteristics: Some(file_device_characteristics),
+    shm_map: Some(file_shm_map),
+    shm_lock: Some(file_shm_lock),
+    shm_barrier: Some(file_shm_barrier),
+    shm_unmap: Some(file_shm_unmap),
+};
+
+extern "C" fn file_close(f: *mut sqlite3_file) -> c_int {
+    unsafe {
+        let raw = f as *mut RawFile;
+        let opened: Box<Box<VfsFile + Send>> = mem::transmute((*raw).file);
+        (*raw).file = ptr::mut_null();
+        catch_panic((), || drop(opened));
+    }
+    SQLITE_OK as c_int
+}
+
+extern "C" fn file_read(f: *mut sqlite3_file, buf: *mut c_void, amt: c_int, offset: i64) -> c_int {
+    unsafe {
+        let opened = file(f);
+        slice::raw::mut_buf_as_slice(buf as *mut u8, amt as uint, |buf| {
+            let r = catch_panic(Err(SQLITE_IOERR), || opened.read(buf, offset as u64));
+            match r {
+                Ok(n) if n >= buf.len() => SQLITE_OK as c_int,
+                Ok(n) => {
+                    for b in buf.mut_slice_from(n).mut_iter() {
+                        *b = 0;
+                    }
+                    SQLITE_IOERR_SHORT_READ
+                }
+                Err(e) => e as c_int
+            }
+        })
+    }
+}
+
+extern "C" fn file_write(f: *mut sqlite3_file, buf: *const c_void, amt: c_int, offset: i64) -> c_int {
+    unsafe {
+        let opened = file(f);
+        slice::raw::buf_as_slice(buf as *const u8, amt as uint, |buf| {
+            result_code(catch_panic(Err(SQLITE_IOERR), || opened.write(buf, offset as u64)))
+        })
+    }
+}
+
+extern "C" fn file_truncate(f: *mut sqlite3_file, size: i64) -> c_int {
+    unsafe {
+        let opened = file(f);
+        result_code(catch_panic(Err(SQLITE_IOERR), || opened.truncate(size as u64)))
+    }
+}
+
+extern "C" fn file_sync(f: *mut sqlite3_file, flags: c_int) -> c_int {
+    unsafe {
+        let opened = file(f);
+        let data_only = flags & SQLITE_SYNC_DATAONLY != 0;
+        result_code(catch_panic(Err(SQLITE_IOERR), || opened.sync(data_only)))
+    }
+}
+
+extern "C" fn file_size(f: *mut sqlite3_file, out: *mut i64) -> c_int {
+    unsafe {
+        let opened = file(f);
+        let r = catch_panic(Err(SQLITE_IOERR), || opened.file_size());
+        match r {
+            Ok(size) => { *out = size as i64; SQLITE_OK as c_int }
+            Err(e) => e as c_int
+        }
+    }
+}
+
+extern "C" fn file_lock(f: *mut sqlite3_file, level: c_int) -> c_int {
+    unsafe {
+        let opened = file(f);
+        let level = FromPrimitive::from_int(level as int).unwrap_or(LockExclusive);
+        result_code(catch_panic(Err(SQLITE_IOERR), || opened.lock(level)))
+    }
+}
+
+extern "C" fn file_unlock(f: *mut sqlite3_file, level: c_int) -> c_int {
+    unsafe {
+        let opened = file(f);
+        let level = FromPrimitive::from_int(level as int).unwrap_or(LockNone);
+        result_code(catch_panic(Err(SQLITE_IOERR), || opened.unlock(level)))
+    }
+}
+
+extern "C" fn file_check_reserved_lock(f: *mut sqlite3_file, out: *mut c_int) -> c_int {
+    unsafe {
+        let opened = file(f);
+        let r = catch_panic(Err(SQLITE_IOERR), || opened.check_reserved_lock());
+        match r {
+            Ok(b) => { *out = b as c_int; SQLITE_OK as c_int }
+            Err(e) => e as c_int
+        }
+    }
+}
+
+extern "C" fn file_control(_f: *mut sqlite3_file, _op: c_int, _arg: *mut c_void) -> c_int {
+    SQLITE_NOTFOUND as c_int
+}
+
+extern "C" fn file_sector_size(f: *mut sqlite3_file) -> c_int {
+    unsafe {
+        let opened = file(f);
+        catch_panic(4096, || opened.sector_size()) as c_int
+    }
+}
+
+extern "C" fn file_device_characteristics(f: *mut sqlite3_file) -> c_int {
+    unsafe {
+        let opened = file(f);
+        catch_panic(0, || opened.device_characteristics()) as c_int
+    }
+}
+
+extern "C" fn file_shm_map(f: *mut sqlite3_file, region: c_int, size: c_int, extend: c_int,
+                           out: *mut *mut c_void) -> c_int {
+    unsafe {
+        let opened = file(f);
+        let r = catch_panic(Err(SQLITE_IOERR), || opened.shm_map(region as uint, size as uint, extend != 0));
+        match r {
+            Ok(p) => { *out = p as *mut c_void; SQLITE_OK as c_int }
+            Err(e) => { *out = ptr::mut_null(); e as c_int }
+        }
+    }
+}
+
+extern "C" fn file_shm_lock(f: *mut sqlite3_file, offset: c_int, n: c_int, flags: c_int) -> c_int {
+    unsafe {
+        let opened = file(f);
+        let lock = flags & SQLITE_SHM_LOCK != 0;
+        let exclusive = flags & SQLITE_SHM_EXCLUSIVE != 0;
+        result_code(catch_panic(Err(SQLITE_IOERR), || {
+            opened.shm_lock(offset as uint, n as uint, lock, exclusive)
+        }))
+    }
+}
+
+extern "C" fn file_shm_barrier(f: *mut sqlite3_file) {
+    unsafe {
+        let opened = file(f);
+        catch_panic((), || opened.shm_barrier());
+    }
+}
+
+extern "C" fn file_shm_unmap(f: *mut sqlite3_file, delete: c_int) -> c_int {
+    unsafe {
+        let opened = file(f);
+        result_code(catch_panic(Err(SQLITE_IOERR), || opened.shm_unmap(delete != 0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::Database;
+    use std::io::{EndOfFile, File, Open, Read, ReadWrite, SeekSet, TempDir};
+    use std::io::fs;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUint, SeqCst};
+    use types::*;
+    use super::*;
+
+    /// Stores files in a directory, without locking.
+    struct DirVfs {
+        root: Path,
+        opened: Arc<AtomicUint>,
+    }
+
+    struct DirFile {
+        file: File,
+        path: Path,
+        delete_on_close: bool,
+    }
+
+    impl Drop for DirFile {
+        fn drop(&mut self) {
+            if self.delete_on_close {
+                let _ = fs::unlink(&self.path);
+            }
+        }
+    }
+
+    impl VfsFile for DirFile {
+        fn read(&mut self, buf: &mut [u8], offset: u64) -> VfsResult<uint> {
+            try!(self.file.seek(offset as i64, SeekSet).map_err(|_| SQLITE_IOERR));
+            let mut n = 0;
+            while n < buf.len() {
+                match self.file.read(buf.mut_slice_from(n)) {
+                    Ok(k) => n += k,
+                    Err(ref e) if e.kind == EndOfFile => break,
+                    Err(_) => return Err(SQLITE_IOERR)
+                }
+            }
+            Ok(n)
+        }
+
+        fn write(&mut self, buf: &[u8], offset: u64) -> VfsResult<()> {
+            try!(self.file.seek(offset as i64, SeekSet).map_err(|_| SQLITE_IOERR));
+            self.file.write(buf).map_err(|_| SQLITE_IOERR)
+        }
+
+        fn truncate(&mut self, size: u64) -> VfsResult<()> {
+            self.file.truncate(size as i64).map_err(|_| SQLITE_IOERR)
+        }
+
+        fn sync(&mut self, data_only: bool) -> VfsResult<()> {
+            if data_only { self.file.datasync() } else { self.file.fsync() }.map_err(|_| SQLITE_IOERR)
+        }
+
+        fn file_size(&mut self) -> VfsResult<u64> {
+            self.file.stat().map(|s| s.size).map_err(|_| SQLITE_IOERR)
+        }
+
+        fn lock(&mut self, _level: LockLevel) -> VfsResult<()> { Ok(()) }
+        fn unlock(&mut self, _level: LockLevel) -> VfsResult<()> { Ok(()) }
+        fn check_reserved_lock(&mut self) -> VfsResult<bool> { Ok(false) }
+    }
+
+    impl Vfs for DirVfs {
+        fn open(&self, name: Option<&str>, flags: OpenFlags) -> VfsResult<Box<VfsFile + Send>> {
+            let n = self.opened.fetch_add(1, SeqCst);
+            let path = match name {
+                Some(name) => Path::new(name),
+                None => self.root.join(format!("temp{}", n)),
+            };
+            let access = if flags.contains(SQLITE_OPEN_READONLY) { Read } else { ReadWrite };
+            if !flags.contains(SQLITE_OPEN_CREATE) && !path.exists() {
+                return Err(SQLITE_CANTOPEN);
+            }
+            let file = try!(File::open_mode(&path, Open, access).map_err(|_| SQLITE_CANTOPEN));
+            Ok(box DirFile {
+                file: file,
+                path: path,
+                delete_on_close: name.is_none() || flags.contains(SQLITE_OPEN_DELETEONCLOSE),
+            } as Box<VfsFile + Send>)
+        }
+
+        fn delete(&self, name: &str, _sync_dir: bool) -> VfsResult<()> {
+            fs::unlink(&Path::new(name)).map_err(|_| SQLITE_IOERR)
+        }
+
+        fn access(&self, name: &str, _check: AccessCheck) -> VfsResult<bool> {
+            Ok(Path::new(name).exists())
+        }
+
+        fn full_pathname(&self, name: &str) -> VfsResult<String> {
+            Ok(self.root.join(name).as_str().unwrap().to_string())
+        }
+    }
+
+    #[test]
+    fn custom_vfs() {
+        let dir = TempDir::new("rustsqlite").unwrap();
+        let opened = Arc::new(AtomicUint::new(0));
+        let vfs = box DirVfs { root: dir.path().clone(), opened: opened.clone() };
+        assert_eq!(register_vfs("dirvfs", vfs as Box<Vfs + Send + Sync>, false), Ok(()));
+
+        {
+            let database = Database::open_with_flags(
+                "test.db", SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE, Some("dirvfs")).unwrap();
+            database.exec("CREATE TABLE test (v TEXT);
+                           BEGIN;
+                           INSERT INTO test VALUES ('hello');
+                           COMMIT;").unwrap();
+        }
+        assert!(opened.load(SeqCst) >= 2); // database and rollback journal
+        assert!(dir.path().join("test.db").exists());
+        assert!(!dir.path().join("test.db-journal").exists());
+
+        assert_eq!(Database::open_with_flags("missing.db", SQLITE_OPEN_READWRITE, Some("dirvfs")).err(),
+                   Some(SQLITE_CANTOPEN));
+
+        let database = Database::open_with_flags("test.db", SQLITE_OPEN_READWRITE, Some("dirvfs")).unwrap();
+        let cursor = database.prepare("SELECT v FROM test").unwrap();
+        assert_eq!(cursor.step(), Ok(SQLITE_ROW));
+        assert_eq!(cursor.get_text(0).as_slice(), "hello");
+    }
+}
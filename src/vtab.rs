@@ -0,0 +1,308 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Virtual tables implemented in Rust.
+//!
+//! A `Module` registered with `Database::create_module` is asked for a
+//! `VirtualTable` by `CREATE VIRTUAL TABLE name USING module(args)` and
+//! whenever a connection opens a database that already has one. Virtual
+//! tables are read-only, and every query scans all of their rows, leaving
+//! `WHERE` clauses and ordering to SQLite.
+//! See http://www.sqlite.org/vtab.html
+
+use libc::{c_char, c_int, c_void};
+use std::mem;
+use std::ptr;
+use std::slice;
+
+use ffi::*;
+use types::*;
+use util::*;
+
+/// Creates the virtual tables of one module.
+pub trait Module {
+    /// Returns the table `name` in database `schema` (`main`, `temp`,
+    /// ...) for the arguments given to `CREATE VIRTUAL TABLE`. It is
+    /// called again with the same arguments each time a connection opens
+    /// the table.
+    fn connect(&mut self, schema: &str, name: &str, args: &[String]) -> Result<Box<VirtualTable + Send>, SqliteError>;
+}
+
+/// One virtual table.
+pub trait VirtualTable {
+    /// Returns the `CREATE TABLE` statement that declares the columns. The
+    /// table name in it is ignored.
+    fn schema(&self) -> String;
+
+    /// Returns every row, as its rowid and its column values. Missing
+    /// values read as `NULL`.
+    fn rows(&mut self) -> Result<Vec<(i64, Vec<BindArg>)>, SqliteError>;
+}
+
+#[repr(C)]
+struct Table {
+    base: sqlite3_vtab,
+    table: Box<VirtualTable + Send>,
+}
+
+#[repr(C)]
+struct TableCursor {
+    base: sqlite3_vtab_cursor,
+    rows: Vec<(i64, Vec<BindArg>)>,
+    pos: uint,
+}
+
+/// Registers `module` on the connection `dbh` under `name`. It is dropped
+/// when it is replaced or the connection is closed.
+/// See http://www.sqlite.org/c3ref/create_module.html
+#[allow(visible_private_types)]
+pub fn create_module(dbh: &*mut dbh, name: &str, module: Box<Module + Send>) -> SqliteResult<()> {
+    let r = name.with_c_str(|_name| unsafe {
+        // SQLite drops the module through `drop_module`, even if
+        // registering it fails.
+        let aux: *mut c_void = mem::transmute(box module);
+        sqlite3_create_module_v2(*dbh, _name, &MODULE, aux, Some(drop_module))
+    });
+    check(r)
+}
+
+static MODULE: sqlite3_module = sqlite3_module {
+    version: 1,
+    create: Some(vtab_connect),
+    connect: Some(vtab_connect),
+    best_index: Some(vtab_best_index),
+    disconnect: Some(vtab_disconnect),
+    destroy: Some(vtab_disconnect),
+    open: Some(vtab_open),
+    close: Some(vtab_close),
+    filter: Some(vtab_filter),
+    next: Some(vtab_next),
+    eof: Some(vtab_eof),
+    column: Some(vtab_column),
+    rowid: Some(vtab_rowid),
+    update: None,
+    begin: None,
+    sync: None,
+    commit: None,
+    rollback: None,
+    find_function: None,
+    rename: None,
+};
+
+extern "C" fn drop_module(aux: *mut c_void) {
+    let _: Box<Box<Module + Send>> = unsafe { mem::transmute(aux) };
+}
+
+/// Reports `e` through the table's error message and returns its code.
+unsafe fn vtab_error(vtab: *mut sqlite3_vtab, e: SqliteError) -> c_int {
+    sqlite3_free((*vtab).err_msg as *mut c_void);
+    (*vtab).err_msg = copy_message(e.desc.as_slice());
+    e.kind as c_int
+}
+
+fn panicked() -> SqliteError {
+    SqliteError { kind: SQLITE_ERROR, desc: "virtual table panicked".to_string() }
+}
+
+extern "C" fn vtab_connect(db: *mut dbh,
+                           aux: *mut c_void,
+                           argc: c_int,
+                           argv: *const *const c_char,
+                           out: *mut *mut sqlite3_vtab,
+                           err: *mut *mut c_char) -> c_int {
+    unsafe {
+        let module: &mut Box<Module + Send> = mem::transmute(aux);
+        // The module name, the database name and the table name come
+        // before the arguments.
+        let args = slice::raw::buf_as_slice(argv, argc as uint, |args| {
+            args.iter().map(|&a| opt_string(a).unwrap_or(String::new())).collect::<Vec<String>>()
+        });
+        let r = catch_panic(Err(panicked()), || {
+            let table = try!(module.connect(args[1].as_slice(), args[2].as_slice(), args.slice_from(3)));
+            let schema = table.schema();
+            let r = schema.with_c_str(|_schema| sqlite3_declare_vtab(db, _schema));
+            if r != SQLITE_OK as c_int {
+                let desc = opt_string(sqlite3_errmsg(db)).unwrap_or(String::new());
+                return Err(SqliteError { kind: check(r).unwrap_err(), desc: desc });
+            }
+            Ok(table)
+        });
+        match r {
+            Ok(table) => {
+                let table = box Table {
+                    base: sqlite3_vtab { module: ptr::null(), n_ref: 0, err_msg: ptr::mut_null() },
+                    table: table,
+                };
+                *out = mem::transmute(table);
+                SQLITE_OK as c_int
+            }
+            Err(e) => {
+                *err = copy_message(e.desc.as_slice());
+                e.kind as c_int
+            }
+        }
+    }
+}
+
+extern "C" fn vtab_best_index(_vtab: *mut sqlite3_vtab, _info: *mut index_info) -> c_int {
+    // Every query is a full scan.
+    SQLITE_OK as c_int
+}
+
+extern "C" fn vtab_disconnect(vtab: *mut sqlite3_vtab) -> c_int {
+    unsafe {
+        let table: Box<Table> = mem::transmute(vtab);
+        sqlite3_free(table.base.err_msg as *mut c_void);
+        catch_panic((), || drop(table));
+    }
+    SQLITE_OK as c_int
+}
+
+extern "C" fn vtab_open(_vtab: *mut sqlite3_vtab, out: *mut *mut sqlite3_vtab_cursor) -> c_int {
+    unsafe {
+        let cursor = box TableCursor {
+            base: sqlite3_vtab_cursor { vtab: ptr::mut_null() },
+            rows: Vec::new(),
+            pos: 0,
+        };
+        *out = mem::transmute(cursor);
+    }
+    SQLITE_OK as c_int
+}
+
+extern "C" fn vtab_close(cursor: *mut sqlite3_vtab_cursor) -> c_int {
+    let _: Box<TableCursor> = unsafe { mem::transmute(cursor) };
+    SQLITE_OK as c_int
+}
+
+extern "C" fn vtab_filter(cursor: *mut sqlite3_vtab_cursor,
+                          _idx_num: c_int,
+                          _idx_str: *const c_char,
+                          _argc: c_int,
+                          _argv: *mut *mut value) -> c_int {
+    unsafe {
+        let cursor = cursor as *mut TableCursor;
+        let vtab = (*cursor).base.vtab;
+        let table = &mut (*(vtab as *mut Table)).table;
+        match catch_panic(Err(panicked()), || table.rows()) {
+            Ok(rows) => {
+                (*cursor).rows = rows;
+                (*cursor).pos = 0;
+                SQLITE_OK as c_int
+            }
+            Err(e) => vtab_error(vtab, e)
+        }
+    }
+}
+
+extern "C" fn vtab_next(cursor: *mut sqlite3_vtab_cursor) -> c_int {
+    unsafe { (*(cursor as *mut TableCursor)).pos += 1; }
+    SQLITE_OK as c_int
+}
+
+extern "C" fn vtab_eof(cursor: *mut sqlite3_vtab_cursor) -> c_int {
+    let cursor = unsafe { &*(cursor as *const TableCursor) };
+    (cursor.pos >= cursor.rows.len()) as c_int
+}
+
+extern "C" fn vtab_column(cursor: *mut sqlite3_vtab_cursor, ctx: *mut context, i: c_int) -> c_int {
+    unsafe {
+        let cursor = &*(cursor as *const TableCursor);
+        let (_, ref values) = cursor.rows.as_slice()[cursor.pos];
+        match values.as_slice().get(i as uint) {
+            Some(v) => set_result(ctx, v),
+            None => sqlite3_result_null(ctx),
+        }
+    }
+    SQLITE_OK as c_int
+}
+
+extern "C" fn vtab_rowid(cursor: *mut sqlite3_vtab_cursor, out: *mut i64) -> c_int {
+    unsafe {
+        let cursor = &*(cursor as *const TableCursor);
+        let (rowid, _) = cursor.rows.as_slice()[cursor.pos];
+        *out = rowid;
+    }
+    SQLITE_OK as c_int
+}
+
+#[cfg(test)]
+mod tests {
+    use database::Database;
+    use types::*;
+    use super::*;
+
+    /// `CREATE VIRTUAL TABLE t USING series(n)` has the rows 1 to n.
+    struct Series;
+
+    struct SeriesTable {
+        n: i64,
+    }
+
+    impl Module for Series {
+        fn connect(&mut self, _schema: &str, _name: &str, args: &[String]) -> Result<Box<VirtualTable + Send>, SqliteError> {
+            match args.get(0).and_then(|a| from_str::<i64>(a.as_slice().trim())) {
+                Some(n) => Ok(box SeriesTable { n: n } as Box<VirtualTable + Send>),
+                None => Err(SqliteError { kind: SQLITE_ERROR, desc: "series needs a length".to_string() })
+            }
+        }
+    }
+
+    impl VirtualTable for SeriesTable {
+        fn schema(&self) -> String {
+            "CREATE TABLE x (value INTEGER, square INTEGER)".to_string()
+        }
+
+        fn rows(&mut self) -> Result<Vec<(i64, Vec<BindArg>)>, SqliteError> {
+            Ok(range(1, self.n + 1).map(|i| (i, vec![Integer64(i), Integer64(i * i)])).collect())
+        }
+    }
+
+    #[test]
+    fn series() {
+        let database = Database::new(":memory:").unwrap();
+        database.create_module("series", Series).unwrap();
+        database.exec("CREATE VIRTUAL TABLE s USING series(4)").unwrap();
+
+        let sth = database.prepare("SELECT rowid, square FROM s WHERE value > 2 ORDER BY value DESC").unwrap();
+        assert_eq!(sth.step(), Ok(SQLITE_ROW));
+        assert_eq!(sth.get_i64(0), 4);
+        assert_eq!(sth.get_i64(1), 16);
+        assert_eq!(sth.step(), Ok(SQLITE_ROW));
+        assert_eq!(sth.get_i64(1), 9);
+        assert_eq!(sth.step(), Ok(SQLITE_DONE));
+        drop(sth);
+
+        let r = database.exec_with_message("CREATE VIRTUAL TABLE bad USING series()");
+        assert_eq!(r.unwrap_err().desc.as_slice(), "series needs a length");
+        assert!(database.exec("INSERT INTO s VALUES (5, 25)").is_err());
+    }
+}
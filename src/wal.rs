@@ -0,0 +1,151 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Running WAL checkpoints in the background.
+
+use std::io::timer::Timer;
+use std::time::Duration;
+
+use database::Database;
+use types::*;
+
+/// Checkpoints a WAL-mode database at a fixed interval on a dedicated
+/// connection and thread, so that writers never pay for checkpoints.
+/// Writers will usually want to turn automatic checkpoints off with
+/// `Database::wal_autocheckpoint(0)`.
+pub struct Checkpointer {
+    stop: Option<Sender<()>>,
+    done: Receiver<()>,
+}
+
+impl Checkpointer {
+    /// Opens a connection to `path` on a new thread and checkpoints it in
+    /// `mode` every `interval`. Fails if the connection cannot be opened,
+    /// or with `SQLITE_ERROR` if the thread fails before it is running.
+    pub fn spawn(path: &str, interval: Duration, mode: CheckpointMode) -> SqliteResult<Checkpointer> {
+        let path = path.to_string();
+        let (stop_tx, stop_rx) = channel();
+        let (done_tx, done_rx) = channel();
+        let (opened_tx, opened_rx) = channel();
+        spawn(proc() {
+            let database = match Database::new(path.as_slice()) {
+                Ok(database) => database,
+                Err(e) => { opened_tx.send(Err(e)); return }
+            };
+            let mut timer = match Timer::new() {
+                Ok(timer) => timer,
+                Err(_) => { opened_tx.send(Err(SQLITE_ERROR)); return }
+            };
+            opened_tx.send(Ok(()));
+            let _ = database.set_busy_timeout(interval);
+            let ticks = timer.periodic(interval);
+            loop {
+                select! {
+                    _ = stop_rx.recv_opt() => break,
+                    () = ticks.recv() => {
+                        let r = database.wal_checkpoint(None, mode);
+                        debug!("`Checkpointer`: {} -> {}", path, r);
+                    }
+                }
+            }
+            drop(database);
+            done_tx.send(());
+        });
+        // A disconnected channel means the thread failed before reporting.
+        match opened_rx.recv_opt() {
+            Ok(r) => try!(r),
+            Err(()) => return Err(SQLITE_ERROR)
+        }
+        Ok(Checkpointer { stop: Some(stop_tx), done: done_rx })
+    }
+
+    /// Stops checkpointing and waits for the connection to close.
+    pub fn stop(self) {
+        // see Drop
+    }
+}
+
+impl Drop for Checkpointer {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        let _ = self.done.recv_opt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::Database;
+    use pragma::JournalWal;
+    use std::io::{fs, timer, TempDir};
+    use std::time::Duration;
+    use types::*;
+    use super::*;
+
+    #[test]
+    fn wal_checkpoint_counts() {
+        let dir = TempDir::new("rustsqlite").unwrap();
+        let path = dir.path().join("wal.db");
+        let database = Database::new(path.as_str().unwrap()).unwrap();
+        assert_eq!(database.set_journal_mode(None, JournalWal), Ok(JournalWal));
+        assert_eq!(database.wal_autocheckpoint(0), Ok(()));
+        database.exec("CREATE TABLE test (v); INSERT INTO test VALUES (1);").unwrap();
+
+        let (log, ckpt) = database.wal_checkpoint(Some("main"), SQLITE_CHECKPOINT_PASSIVE).unwrap();
+        assert!(log > 0);
+        assert_eq!(log, ckpt);
+        assert!(database.wal_checkpoint(Some("nosuch"), SQLITE_CHECKPOINT_PASSIVE).is_err());
+    }
+
+    #[test]
+    fn background_checkpointer() {
+        let dir = TempDir::new("rustsqlite").unwrap();
+        let path = dir.path().join("wal.db");
+        let wal = dir.path().join("wal.db-wal");
+        let database = Database::new(path.as_str().unwrap()).unwrap();
+        assert_eq!(database.set_journal_mode(None, JournalWal), Ok(JournalWal));
+        assert_eq!(database.wal_autocheckpoint(0), Ok(()));
+
+        let checkpointer = Checkpointer::spawn(path.as_str().unwrap(),
+                                               Duration::milliseconds(10),
+                                               SQLITE_CHECKPOINT_TRUNCATE).unwrap();
+        database.exec("CREATE TABLE test (v); INSERT INTO test VALUES (1);").unwrap();
+        assert!(fs::stat(&wal).unwrap().size > 0);
+
+        // wait for a checkpoint, giving up after about ten seconds
+        let mut tries = 0u;
+        while fs::stat(&wal).unwrap().size > 0 && tries < 1000 {
+            timer::sleep(Duration::milliseconds(10));
+            tries += 1;
+        }
+        checkpointer.stop();
+        assert_eq!(fs::stat(&wal).unwrap().size, 0);
+    }
+}
@@ -0,0 +1,213 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! A connection owned by a background thread.
+//!
+//! `DatabaseWorker` runs jobs against its connection one at a time, in the
+//! order they were submitted, so that callers such as GUI event loops never
+//! block on SQLite. Each job's result comes back on its own receiver.
+
+use std::any::{Any, AnyRefExt};
+use std::rt::unwind;
+
+use database::Database;
+use types::*;
+
+/// Why a job did not produce a result.
+#[deriving(PartialEq, Eq, Show, Clone)]
+pub enum WorkerError {
+    /// The job failed; carries the failure message. The worker rolls back
+    /// any transaction the job left open and carries on.
+    WorkerPanicked(String),
+    /// The worker has shut down.
+    WorkerStopped,
+    /// The worker could not open its connection.
+    WorkerOpenFailed(ResultError),
+    /// The job was cancelled before it ran.
+    WorkerCancelled,
+}
+
+pub type WorkerResult<T> = Result<T, WorkerError>;
+
+type Job = proc(&Database): Send;
+
+/// A connection running on a dedicated thread.
+pub struct DatabaseWorker {
+    jobs: Option<Sender<Job>>,
+    done: Receiver<()>,
+}
+
+fn panic_message(cause: Box<Any + Send>) -> String {
+    match cause.as_ref::<&'static str>() {
+        Some(s) => return s.to_string(),
+        None => {}
+    }
+    match cause.as_ref::<String>() {
+        Some(s) => s.clone(),
+        None => "unknown failure".to_string()
+    }
+}
+
+impl DatabaseWorker {
+    /// Opens `path` on a new thread.
+    pub fn spawn(path: &str) -> WorkerResult<DatabaseWorker> {
+        let path = path.to_string();
+        DatabaseWorker::spawn_with(proc() Database::new(path.as_slice()))
+    }
+
+    /// Runs `open` on a new thread and serves the connection it returns.
+    /// Fails with `WorkerOpenFailed` if `open` returns an error and with
+    /// `WorkerPanicked` if it fails.
+    pub fn spawn_with(open: proc(): Send -> SqliteResult<Database>) -> WorkerResult<DatabaseWorker> {
+        let (jobs_tx, jobs_rx) = channel::<Job>();
+        let (done_tx, done_rx) = channel();
+        let (opened_tx, opened_rx) = channel();
+        spawn(proc() {
+            let mut open = Some(open);
+            let mut out = None;
+            let r = unsafe { unwind::try(|| out = Some(open.take().unwrap()())) };
+            let database = match (r, out) {
+                (Ok(()), Some(Ok(database))) => { opened_tx.send(Ok(())); database }
+                (Ok(()), Some(Err(e))) => { opened_tx.send(Err(WorkerOpenFailed(e))); return }
+                (Err(cause), _) => { opened_tx.send(Err(WorkerPanicked(panic_message(cause)))); return }
+                (Ok(()), None) => unreachable!()
+            };
+            for job in jobs_rx.iter() {
+                job(&database);
+            }
+            drop(database);
+            done_tx.send(());
+        });
+        match opened_rx.recv_opt() {
+            Ok(r) => try!(r),
+            Err(()) => return Err(WorkerPanicked("worker thread exited while opening".to_string()))
+        }
+        Ok(DatabaseWorker { jobs: Some(jobs_tx), done: done_rx })
+    }
+
+    /// Queues `f` to run against the connection and returns a receiver for
+    /// its result.
+    pub fn call<T: Send>(&self, f: proc(&Database): Send -> T) -> Receiver<WorkerResult<T>> {
+        let (tx, rx) = channel();
+        let reply = tx.clone();
+        let job = proc(database: &Database) {
+            let mut f = Some(f);
+            let mut out = None;
+            let r = unsafe { unwind::try(|| out = Some(f.take().unwrap()(database))) };
+            let result = match (r, out) {
+                (Ok(()), Some(v)) => Ok(v),
+                (Err(cause), _) => {
+                    if !database.is_autocommit() {
+                        let _ = database.exec("ROLLBACK");
+                    }
+                    Err(WorkerPanicked(panic_message(cause)))
+                }
+                (Ok(()), None) => unreachable!()
+            };
+            let _ = reply.send_opt(result);
+        };
+        match self.jobs.as_ref().unwrap().send_opt(job) {
+            Ok(()) => {}
+            Err(_) => { let _ = tx.send_opt(Err(WorkerStopped)); }
+        }
+        rx
+    }
+
+    /// Waits for the queued jobs to finish, then closes the connection.
+    pub fn shutdown(self) {
+        // see Drop
+    }
+}
+
+impl Drop for DatabaseWorker {
+    fn drop(&mut self) {
+        drop(self.jobs.take());
+        let _ = self.done.recv_opt();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use database::Database;
+    use types::*;
+    use super::*;
+
+    #[test]
+    fn ordered_jobs() {
+        let worker = DatabaseWorker::spawn(":memory:").unwrap();
+        let create = worker.call(proc(db: &Database) db.exec("CREATE TABLE test (v INTEGER)"));
+        let inserts = range(0i, 10).map(|i| {
+            worker.call(proc(db: &Database) {
+                db.exec(format!("INSERT INTO test VALUES ({})", i).as_slice())
+            })
+        }).collect::<Vec<Receiver<WorkerResult<SqliteResult<()>>>>>();
+        let sum = worker.call(proc(db: &Database) {
+            let cursor = db.prepare("SELECT sum(v) FROM test").unwrap();
+            cursor.step().unwrap();
+            cursor.get_int(0)
+        });
+
+        assert_eq!(create.recv(), Ok(Ok(())));
+        for r in inserts.iter() {
+            assert_eq!(r.recv(), Ok(Ok(())));
+        }
+        assert_eq!(sum.recv(), Ok(45));
+        worker.shutdown();
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        let worker = DatabaseWorker::spawn(":memory:").unwrap();
+        let r = worker.call(proc(db: &Database) {
+            db.exec("CREATE TABLE test (v); BEGIN; INSERT INTO test VALUES (1);").unwrap();
+            fail!("boom");
+        });
+        assert_eq!(r.recv(), Err::<(), WorkerError>(WorkerPanicked("boom".to_string())));
+
+        let r = worker.call(proc(db: &Database) {
+            let cursor = db.prepare("SELECT count(*) FROM test").unwrap();
+            cursor.step().unwrap();
+            (db.is_autocommit(), cursor.get_int(0))
+        });
+        assert_eq!(r.recv(), Ok((true, 0)));
+    }
+
+    #[test]
+    fn open_failure() {
+        assert_eq!(DatabaseWorker::spawn("/nonexistent/dir/test.db").err(), Some(WorkerOpenFailed(SQLITE_CANTOPEN)));
+    }
+
+    #[test]
+    fn open_panics() {
+        let r = DatabaseWorker::spawn_with(proc() -> SqliteResult<Database> { fail!("no disk") });
+        assert_eq!(r.err(), Some(WorkerPanicked("no disk".to_string())));
+    }
+}
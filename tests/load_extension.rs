@@ -0,0 +1,124 @@
+/*
+** Copyright (c) 2011, Brian Smith <brian@linuxfood.net>
+** All rights reserved.
+**
+** Redistribution and use in source and binary forms, with or without
+** modification, are permitted provided that the following conditions are met:
+**
+**   * Redistributions of source code must retain the above copyright notice,
+**     this list of conditions and the following disclaimer.
+**
+**   * Redistributions in binary form must reproduce the above copyright notice,
+**     this list of conditions and the following disclaimer in the documentation
+**     and/or other materials provided with the distribution.
+**
+**   * Neither the name of Brian Smith nor the names of its contributors
+**     may be used to endorse or promote products derived from this software
+**     without specific prior written permission.
+**
+** THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+** AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+** IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE
+** ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE
+** LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR
+** CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF
+** SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS
+** INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN
+** CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE)
+** ARISING IN ANY WAY OUT OF THE USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE
+** POSSIBILITY OF SUCH DAMAGE.
+*/
+
+//! Builds the extension in examples/extension, which calls SQLite only
+//! through the API routines it is handed, and loads it into a connection
+//! of this process and into the stock `sqlite3` shell.
+
+extern crate sqlite3;
+
+use sqlite3::*;
+use std::io::Command;
+use std::io::fs::PathExtensions;
+use std::os;
+
+/// Builds the extension and returns the path of its shared library.
+fn build_extension() -> Path {
+    let root = os::getcwd().join("examples").join("extension");
+    let cargo = os::getenv("CARGO").unwrap_or("cargo".to_string());
+    let out = Command::new(cargo)
+                      .arg("build")
+                      .arg("--manifest-path").arg(root.join("Cargo.toml"))
+                      .output()
+                      .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(out.error.as_slice()));
+
+    let name = format!("{}testext{}", os::consts::DLL_PREFIX, os::consts::DLL_SUFFIX);
+    let candidates = [root.join("target").join(name.as_slice()),
+                      root.join("target").join("debug").join(name.as_slice())];
+    match candidates.iter().find(|p| p.exists()) {
+        Some(p) => p.clone(),
+        None => fail!("{} was not built", name)
+    }
+}
+
+#[test]
+fn load_extension() {
+    let lib = build_extension();
+    let database = Database::new(":memory:").unwrap();
+    database.load_extension(lib.as_str().unwrap(), Some("sqlite3_testext_init")).unwrap();
+
+    let sth = database.prepare("SELECT halve(5), halve(x'00')").unwrap();
+    assert_eq!(sth.step(), Err(SQLITE_MISMATCH));
+    assert_eq!(database.get_errmsg().as_slice(), "halve needs a number");
+    drop(sth);
+
+    database.exec("CREATE VIRTUAL TABLE s USING series(3);
+                   CREATE TABLE t (v TEXT);
+                   INSERT INTO t VALUES ('a'), ('c'), ('b');").unwrap();
+    let sth = database.prepare("SELECT halve(sum(value)) FROM s").unwrap();
+    assert_eq!(sth.step(), Ok(SQLITE_ROW));
+    assert_eq!(sth.get_f64(0), 3.0);
+    drop(sth);
+
+    let sth = database.prepare("SELECT v FROM t ORDER BY v COLLATE reverse").unwrap();
+    let mut seen = Vec::new();
+    while sth.step() == Ok(SQLITE_ROW) {
+        seen.push(sth.get_text(0));
+    }
+    assert_eq!(seen, vec!["c".to_string(), "b".to_string(), "a".to_string()]);
+}
+
+/// Loads the extension with `.load` in the `sqlite3` shell named by
+/// `SQLITE3_SHELL`, or the one on the `PATH`. The shell links its own
+/// SQLite, so this only works if the extension uses nothing but the API
+/// routines.
+#[test]
+fn load_extension_in_shell() {
+    let lib = build_extension();
+    let shell = os::getenv("SQLITE3_SHELL").unwrap_or("sqlite3".to_string());
+    let mut process = match Command::new(shell.as_slice()).arg(":memory:").spawn() {
+        Ok(process) => process,
+        Err(e) => {
+            // Not every machine has the shell; say so instead of failing.
+            println!("skipping load_extension_in_shell, cannot run {}: {}", shell, e);
+            return
+        }
+    };
+    // Dot-commands have to start at the beginning of a line.
+    let script = format!(".bail on\n\
+                          .load {} sqlite3_testext_init\n\
+                          SELECT halve(5);\n\
+                          CREATE VIRTUAL TABLE s USING series(3);\n\
+                          SELECT group_concat(value, ',') FROM s;\n\
+                          SELECT column1 FROM (VALUES ('a'), ('c'), ('b')) ORDER BY column1 COLLATE reverse;\n",
+                         lib.display());
+    {
+        let mut stdin = process.stdin.take().unwrap();
+        stdin.write_str(script.as_slice()).unwrap();
+    }
+    let out = process.wait_with_output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(out.error.as_slice()));
+    let lines: Vec<String> = String::from_utf8_lossy(out.output.as_slice())
+                                   .as_slice().lines().map(|l| l.to_string()).collect();
+    assert_eq!(lines, vec!["2.5".to_string(), "1,2,3".to_string(),
+                          "c".to_string(), "b".to_string(), "a".to_string()]);
+}